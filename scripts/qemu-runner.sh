@@ -0,0 +1,25 @@
+#!/bin/sh
+# Cargo runner for the kernel target: boots the kernel binary cargo built (e.g. the test binary
+# of `cargo ktest`) in QEMU and turns the isa-debug-exit status into a normal exit status.
+#
+# QEMU exits with 33 for ExitCode::Success and 35 for ExitCode::Failed. A triple fault ends QEMU
+# with 0 because of -no-reboot, which counts as a failure as well.
+
+kernel="$(realpath "$1")"
+cd "$(dirname "$0")/.." || exit 1
+
+make -s kernel-iso KERNEL="$kernel" > /dev/null || exit 1
+
+timeout "${QEMU_TIMEOUT:-120}" qemu-system-x86_64 \
+	-cdrom out/yashima-test.iso \
+	-serial stdio \
+	-display none \
+	-no-reboot \
+	-device isa-debug-exit,iobase=0xf4,iosize=0x04
+status=$?
+
+case $status in
+	33) exit 0 ;;
+	124) echo "qemu timed out after ${QEMU_TIMEOUT:-120}s"; exit 1 ;;
+	*) echo "qemu exited with $status"; exit 1 ;;
+esac
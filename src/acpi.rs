@@ -0,0 +1,124 @@
+// Finds the ACPI tables through the RSDP limine hands us, logs every one of them and keeps the
+// parsed tables the drivers need. The parsing itself lives in `yashima_core::acpi`.
+
+use core::slice;
+
+use spin::Once;
+use yashima_core::acpi::fadt::Fadt;
+use yashima_core::acpi::hpet::Hpet;
+use yashima_core::acpi::madt::Madt;
+use yashima_core::acpi::mcfg::Mcfg;
+use yashima_core::acpi::{parse_root_table, AcpiError, Rsdp, SdtHeader};
+use yashima_core::paging;
+use yashima_core::paging::PhysAddr;
+
+use crate::{debug, error, info, warn};
+
+static TABLES: Once<Tables> = Once::new();
+
+/// The tables the kernel uses, `None` if the firmware doesn't provide one or it is broken.
+#[derive(Debug)]
+pub struct Tables {
+    pub madt: Option<Madt>,
+    pub fadt: Option<Fadt>,
+    pub hpet: Option<Hpet>,
+    pub mcfg: Option<Mcfg>,
+}
+
+/// Walks the RSDT or XSDT behind `rsdp` and parses the tables we know.
+///
+/// The tables are read through the hhdm, limine maps the ACPI memory there. The entries of the
+/// MADT and MCFG are collected on the kernel heap, so [`crate::mem::heap::init`] has to run first.
+pub fn init(rsdp: *const ()) {
+    let mut tables = Tables {
+        madt: None,
+        fadt: None,
+        hpet: None,
+        mcfg: None,
+    };
+    if let Err(e) = unsafe { walk(rsdp, &mut tables) } {
+        error!("couldn't read the acpi tables: {e:?}");
+    }
+    TABLES.call_once(|| tables);
+}
+
+/// The parsed tables, `None` before [`init`].
+pub fn tables() -> Option<&'static Tables> {
+    TABLES.get()
+}
+
+unsafe fn walk(rsdp: *const (), tables: &mut Tables) -> Result<(), AcpiError> {
+    // older base revisions hand out the rsdp in the hhdm, newer ones its physical address
+    let rsdp_addr = match rsdp as u64 {
+        addr if addr >= paging::hhdm_offset() => addr - paging::hhdm_offset(),
+        addr => addr,
+    };
+    let rsdp = Rsdp::parse(phys_slice(rsdp_addr, Rsdp::SIZE))?;
+    info!(
+        "acpi: RSDP at {rsdp_addr:#x}, revision {}, oem {}",
+        rsdp.revision,
+        rsdp.oem_id()
+    );
+
+    let root_addr = rsdp.root_table_address();
+    let root = table_at(root_addr)?;
+    log_table(root_addr, root);
+    for addr in parse_root_table(root)? {
+        let table = match table_at(addr) {
+            Ok(table) => table,
+            Err(e) => {
+                warn!("acpi: unreadable table at {addr:#x}: {e:?}");
+                continue;
+            }
+        };
+        let header = log_table(addr, table);
+        match &header.signature {
+            b"APIC" => tables.madt = parsed(&header, Madt::parse(table)),
+            b"FACP" => tables.fadt = parsed(&header, Fadt::parse(table)),
+            b"HPET" => tables.hpet = parsed(&header, Hpet::parse(table)),
+            b"MCFG" => tables.mcfg = parsed(&header, Mcfg::parse(table)),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn log_table(addr: u64, table: &[u8]) -> SdtHeader {
+    // `table_at` already parsed the header once
+    let header = SdtHeader::parse(table).unwrap();
+    info!(
+        "acpi: {} at {addr:#x}, {} bytes, revision {}, oem {} {}",
+        header.signature(),
+        header.length,
+        header.revision,
+        header.oem_id(),
+        header.oem_table_id()
+    );
+    header
+}
+
+fn parsed<T: core::fmt::Debug>(header: &SdtHeader, table: Result<T, AcpiError>) -> Option<T> {
+    match table {
+        Ok(table) => {
+            debug!("acpi: {table:#x?}");
+            Some(table)
+        }
+        Err(e) => {
+            warn!("acpi: invalid {}: {e:?}", header.signature());
+            None
+        }
+    }
+}
+
+/// The complete table at the physical address `addr`, as long as its header says.
+unsafe fn table_at(addr: u64) -> Result<&'static [u8], AcpiError> {
+    let header = SdtHeader::parse(phys_slice(addr, SdtHeader::SIZE))?;
+    if (header.length as usize) < SdtHeader::SIZE {
+        return Err(AcpiError::BadEntry);
+    }
+    Ok(phys_slice(addr, header.length as usize))
+}
+
+unsafe fn phys_slice(addr: u64, len: usize) -> &'static [u8] {
+    slice::from_raw_parts(PhysAddr::new(addr).to_virt().as_ptr(), len)
+}
@@ -0,0 +1,343 @@
+// The local APIC of the processor, it delivers the interrupts to the core and contains a timer.
+// For the registers refer to chapter 11 "Advanced Programmable Interrupt Controller (APIC)" in
+// the Intel SDM Volume 3A.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Once;
+use yashima_core::bit;
+
+use crate::arch::x86_64::cpuid::CpuId;
+use crate::arch::x86_64::msr::{rdmsr, wrmsr, IA32_APIC_BASE, IA32_TSC_DEADLINE, X2APIC_BASE};
+use crate::arch::x86_64::{idt, trap};
+use crate::drivers::pit;
+use crate::mem::mmio::{ioremap, CacheType, MmioRegion};
+use crate::{debug, info, trace, warn};
+
+/// Vector of the timer interrupt.
+pub const TIMER_VECTOR: u8 = 0xfd;
+/// Vector of the error interrupt, raised when the APIC detects e.g. an illegal vector.
+pub const ERROR_VECTOR: u8 = 0xfe;
+/// Vector the APIC raises when an interrupt vanishes before it could be delivered. It sits at
+/// the top, older processors force the low 4 bits to 1.
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+// register offsets in the xAPIC MMIO page
+// in x2APIC mode the register at `offset` is the MSR X2APIC_BASE + offset / 16
+const ID: u32 = 0x20;
+const VERSION: u32 = 0x30;
+const TASK_PRIORITY: u32 = 0x80;
+const EOI: u32 = 0xb0;
+const SPURIOUS: u32 = 0xf0;
+const ERROR_STATUS: u32 = 0x280;
+const LVT_TIMER: u32 = 0x320;
+const LVT_ERROR: u32 = 0x370;
+const TIMER_INITIAL_COUNT: u32 = 0x380;
+const TIMER_CURRENT_COUNT: u32 = 0x390;
+const TIMER_DIVIDE: u32 = 0x3e0;
+
+const XAPIC_MMIO_SIZE: usize = 0x400;
+
+// IA32_APIC_BASE
+const BASE_X2APIC_ENABLE: u64 = bit!(10);
+const BASE_ENABLE: u64 = bit!(11);
+
+// spurious interrupt vector register
+const SOFTWARE_ENABLE: u32 = bit!(8);
+
+// local vector table entries
+const LVT_MASKED: u32 = bit!(16);
+const LVT_TIMER_PERIODIC: u32 = bit!(17);
+const LVT_TIMER_TSC_DEADLINE: u32 = bit!(18);
+
+/// Divides the bus clock by 16 before it reaches the timer.
+const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+
+/// How long the timer is measured against the PIT.
+const CALIBRATION_MS: u64 = 10;
+
+// CPUID.1
+const CPUID_EDX_APIC: u64 = bit!(9);
+const CPUID_ECX_X2APIC: u64 = bit!(21);
+const CPUID_ECX_TSC_DEADLINE: u64 = bit!(24);
+
+static LAPIC: Once<LocalApic> = Once::new();
+
+static TIMER_TICKS: AtomicU64 = AtomicU64::new(0);
+static ERRORS: AtomicU64 = AtomicU64::new(0);
+static SPURIOUS_COUNT: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApicError {
+    /// CPUID doesn't report a local APIC.
+    NotPresent,
+    /// The processor doesn't support the TSC-deadline mode of the timer.
+    TscDeadlineUnsupported,
+}
+
+/// Operating mode of the timer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerMode {
+    /// Fires once after the programmed time.
+    OneShot,
+    /// Fires every time the programmed time has passed.
+    Periodic,
+    /// Fires once the TSC reaches the value in IA32_TSC_DEADLINE.
+    TscDeadline,
+}
+
+/// How the registers of the APIC are reached.
+enum Access {
+    /// Memory mapped registers at the base from IA32_APIC_BASE.
+    XApic(MmioRegion),
+    /// Registers in the MSRs from [`X2APIC_BASE`] on.
+    X2Apic,
+}
+
+pub struct LocalApic {
+    access: Access,
+    /// Timer ticks per millisecond with [`TIMER_DIVIDE_BY_16`].
+    ticks_per_ms: u64,
+    tsc_deadline: bool,
+}
+
+impl LocalApic {
+    fn read(&self, reg: u32) -> u32 {
+        match &self.access {
+            Access::XApic(mmio) => mmio.read32(reg as usize),
+            // the upper half of the MSRs is reserved, except for the ICR which we don't read here
+            Access::X2Apic => unsafe { rdmsr(X2APIC_BASE + reg / 16) as u32 },
+        }
+    }
+
+    fn write(&self, reg: u32, value: u32) {
+        match &self.access {
+            Access::XApic(mmio) => mmio.write32(reg as usize, value),
+            Access::X2Apic => unsafe { wrmsr(X2APIC_BASE + reg / 16, value as u64) },
+        }
+    }
+
+    /// The APIC ID of this processor.
+    pub fn id(&self) -> u32 {
+        match self.access {
+            // the xAPIC keeps the 8 bit ID in the top byte
+            Access::XApic(_) => self.read(ID) >> 24,
+            Access::X2Apic => self.read(ID),
+        }
+    }
+
+    pub fn is_x2apic(&self) -> bool {
+        matches!(self.access, Access::X2Apic)
+    }
+
+    pub fn supports_tsc_deadline(&self) -> bool {
+        self.tsc_deadline
+    }
+
+    /// Signals the end of the handler for the interrupt in service.
+    pub fn end_of_interrupt(&self) {
+        self.write(EOI, 0);
+    }
+
+    /// Fires [`TIMER_VECTOR`] once after `us` microseconds.
+    pub fn start_oneshot(&self, us: u64) {
+        self.start_timer(0, us);
+    }
+
+    /// Fires [`TIMER_VECTOR`] every `us` microseconds until the timer is stopped.
+    pub fn start_periodic(&self, us: u64) {
+        self.start_timer(LVT_TIMER_PERIODIC, us);
+    }
+
+    /// Fires [`TIMER_VECTOR`] once the TSC reaches `deadline`. A deadline in the past fires
+    /// right away.
+    pub fn start_tsc_deadline(&self, deadline: u64) -> Result<(), ApicError> {
+        if !self.tsc_deadline {
+            return Err(ApicError::TscDeadlineUnsupported);
+        }
+        self.write(LVT_TIMER, LVT_TIMER_TSC_DEADLINE | TIMER_VECTOR as u32);
+        unsafe {
+            // the SDM asks for a fence between switching the mode and writing the deadline,
+            // otherwise the write can be ignored
+            core::arch::asm!("mfence", options(nostack, preserves_flags));
+            wrmsr(IA32_TSC_DEADLINE, deadline);
+        }
+        Ok(())
+    }
+
+    /// Stops the timer in any mode.
+    pub fn stop_timer(&self) {
+        if self.timer_mode() == TimerMode::TscDeadline {
+            unsafe { wrmsr(IA32_TSC_DEADLINE, 0) };
+        }
+        self.write(LVT_TIMER, LVT_MASKED | TIMER_VECTOR as u32);
+        self.write(TIMER_INITIAL_COUNT, 0);
+    }
+
+    pub fn timer_mode(&self) -> TimerMode {
+        // bits 17-18 of the LVT entry
+        match (self.read(LVT_TIMER) >> 17) & 0b11 {
+            0 => TimerMode::OneShot,
+            1 => TimerMode::Periodic,
+            _ => TimerMode::TscDeadline,
+        }
+    }
+
+    fn start_timer(&self, mode: u32, us: u64) {
+        let ticks = (self.ticks_per_ms * us / 1000).clamp(1, u32::MAX as u64);
+        self.write(TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+        self.write(LVT_TIMER, mode | TIMER_VECTOR as u32);
+        // writing the initial count starts the countdown
+        self.write(TIMER_INITIAL_COUNT, ticks as u32);
+    }
+}
+
+/// Enables the local APIC of the bootstrap processor, in x2APIC mode if the processor supports
+/// it, and calibrates the timer against the PIT.
+///
+/// The IDT has to be loaded and, for the xAPIC, the frame allocator initialized. LINT0 stays as
+/// the firmware set it up, usually as virtual wire to the legacy PICs, so their interrupts keep
+/// arriving until the IOAPIC takes over.
+pub fn init() -> Result<(), ApicError> {
+    let features = CpuId::get_cpuid_eax(1);
+    if features.edx & CPUID_EDX_APIC == 0 {
+        return Err(ApicError::NotPresent);
+    }
+    let x2apic = features.ecx & CPUID_ECX_X2APIC != 0;
+    let tsc_deadline = features.ecx & CPUID_ECX_TSC_DEADLINE != 0;
+
+    let base = unsafe { rdmsr(IA32_APIC_BASE) };
+    let phys = base & !0xfff;
+    let access = if x2apic {
+        // x2APIC mode can only be entered from the enabled xAPIC mode
+        unsafe {
+            wrmsr(IA32_APIC_BASE, base | BASE_ENABLE);
+            wrmsr(IA32_APIC_BASE, base | BASE_ENABLE | BASE_X2APIC_ENABLE);
+        }
+        Access::X2Apic
+    } else {
+        unsafe {
+            wrmsr(IA32_APIC_BASE, base | BASE_ENABLE);
+            Access::XApic(ioremap(phys, XAPIC_MMIO_SIZE, CacheType::Uncacheable))
+        }
+    };
+
+    {
+        let mut idt = idt::IDT.lock();
+        for vector in [TIMER_VECTOR, ERROR_VECTOR, SPURIOUS_VECTOR] {
+            unsafe { idt.set_raw_handler(vector, trap::stub_addr(vector)) };
+        }
+    }
+
+    let mut lapic = LocalApic {
+        access,
+        ticks_per_ms: 0,
+        tsc_deadline,
+    };
+    // accept interrupts of every priority
+    lapic.write(TASK_PRIORITY, 0);
+    lapic.write(SPURIOUS, SOFTWARE_ENABLE | SPURIOUS_VECTOR as u32);
+    // unmasked once `local` works, the handler needs it
+    lapic.write(LVT_ERROR, LVT_MASKED | ERROR_VECTOR as u32);
+    // the error status only updates on a write, the first write also clears stale errors
+    lapic.write(ERROR_STATUS, 0);
+    lapic.write(ERROR_STATUS, 0);
+    lapic.end_of_interrupt();
+
+    lapic.ticks_per_ms = calibrate_timer(&lapic);
+    info!(
+        "local apic {} at {:#x} in {} mode, version {:#x}, timer {} kHz, tsc-deadline: {}",
+        lapic.id(),
+        phys,
+        if x2apic { "x2apic" } else { "xapic" },
+        lapic.read(VERSION) & 0xff,
+        lapic.ticks_per_ms,
+        tsc_deadline
+    );
+    LAPIC
+        .call_once(|| lapic)
+        .write(LVT_ERROR, ERROR_VECTOR as u32);
+    Ok(())
+}
+
+/// Counts the ticks of the masked timer while the PIT waits [`CALIBRATION_MS`].
+fn calibrate_timer(lapic: &LocalApic) -> u64 {
+    lapic.write(TIMER_DIVIDE, TIMER_DIVIDE_BY_16);
+    lapic.write(LVT_TIMER, LVT_MASKED | TIMER_VECTOR as u32);
+    lapic.write(TIMER_INITIAL_COUNT, u32::MAX);
+    pit::wait_ms(CALIBRATION_MS);
+    let elapsed = u32::MAX - lapic.read(TIMER_CURRENT_COUNT);
+    lapic.write(TIMER_INITIAL_COUNT, 0);
+    debug!("apic timer: {elapsed} ticks in {CALIBRATION_MS}ms");
+    (elapsed as u64 / CALIBRATION_MS).max(1)
+}
+
+/// The local APIC of this processor. Panics if [`init`] didn't succeed.
+pub fn local() -> &'static LocalApic {
+    LAPIC.get().expect("the local apic is not initialized")
+}
+
+pub fn is_initialized() -> bool {
+    LAPIC.get().is_some()
+}
+
+/// Number of timer interrupts since boot.
+pub fn timer_ticks() -> u64 {
+    TIMER_TICKS.load(Ordering::Relaxed)
+}
+
+/// Number of error interrupts since boot.
+pub fn error_count() -> u64 {
+    ERRORS.load(Ordering::Relaxed)
+}
+
+/// Number of spurious interrupts since boot.
+pub fn spurious_count() -> u64 {
+    SPURIOUS_COUNT.load(Ordering::Relaxed)
+}
+
+/// Handles an interrupt on one of the vectors of the local APIC itself.
+///
+/// The vectors are installed before [`init`] is done. Until then only spurious interrupts can
+/// arrive, the timer and the error interrupt stay masked.
+pub fn handle(vector: u8) {
+    if vector == SPURIOUS_VECTOR {
+        // not in service, so there is nothing to acknowledge
+        SPURIOUS_COUNT.fetch_add(1, Ordering::Relaxed);
+        trace!("spurious apic interrupt");
+        return;
+    }
+    let lapic = local();
+    match vector {
+        TIMER_VECTOR => {
+            TIMER_TICKS.fetch_add(1, Ordering::Relaxed);
+        }
+        ERROR_VECTOR => {
+            ERRORS.fetch_add(1, Ordering::Relaxed);
+            lapic.write(ERROR_STATUS, 0);
+            warn!("apic error, status {:#x}", lapic.read(ERROR_STATUS));
+        }
+        _ => unreachable!("vector {vector} doesn't belong to the local apic"),
+    }
+    lapic.end_of_interrupt();
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use super::*;
+    use crate::time::Instant;
+
+    #[test_case]
+    fn oneshot_timer_fires() {
+        let before = timer_ticks();
+        local().start_oneshot(1000);
+        // a lost interrupt has to fail the test instead of hanging the run
+        let deadline = Instant::now() + Duration::from_millis(100);
+        while timer_ticks() == before && Instant::now() < deadline {
+            core::hint::spin_loop();
+        }
+        assert!(timer_ticks() > before, "the timer didn't fire within 100ms");
+    }
+}
@@ -1,6 +1,7 @@
-use crate::bit_utils::BitRange;
 use core::arch::asm;
 
+use yashima_core::bit_utils::BitRange;
+
 /// Raw contents of Cr0 register.
 ///
 /// For further information refer to [3.1.1 Cr0 Register](https://www.amd.com/content/dam/amd/en/documents/processor-tech-docs/programmer-references/24593.pdf#page=102) in the AMD Manual Volume 2.
@@ -69,7 +69,7 @@ impl CpuId {
 }
 
 
-use crate::bit_utils::BitRange;
+use yashima_core::bit_utils::BitRange;
 
 /// Returns the processors supported physical address size and current virtual address size as
 /// (phys_range, virt_range)
@@ -0,0 +1,315 @@
+use core::fmt;
+
+use yashima_core::bit_utils::BitRange;
+use yashima_core::mem::page::PageSize;
+use yashima_core::paging::VirtAddr;
+
+use crate::arch::x86_64::idt::{vector, GateDescriptorType, Ist, PageFaultErrorCode};
+use crate::arch::x86_64::trap::TrapFrame;
+use crate::arch::x86_64::{gdt, halt, idt, trap};
+use crate::debug::backtrace;
+use crate::klog::Level;
+use crate::mem::vmalloc::FaultError;
+use crate::mem::{cow, vmalloc};
+use crate::{error, klog};
+
+/// The exceptions from #DE to #CP and #SX, vectors 9 and 15 are reserved.
+const EXCEPTIONS: [u8; 21] = [
+    vector::DIVIDE_ERROR,
+    vector::DEBUG,
+    vector::NMI,
+    vector::BREAKPOINT,
+    vector::OVERFLOW,
+    vector::BOUND_RANGE,
+    vector::INVALID_OPCODE,
+    vector::DEVICE_NOT_AVAILABLE,
+    vector::DOUBLE_FAULT,
+    vector::INVALID_TSS,
+    vector::SEGMENT_NOT_PRESENT,
+    vector::STACK_FAULT,
+    vector::GENERAL_PROTECTION,
+    vector::PAGE_FAULT,
+    vector::X87_FLOATING_POINT,
+    vector::ALIGNMENT_CHECK,
+    vector::MACHINE_CHECK,
+    vector::SIMD_FLOATING_POINT,
+    vector::VIRTUALIZATION,
+    vector::CONTROL_PROTECTION,
+    vector::SECURITY,
+];
+
+/// Installs the entry stubs for every architectural exception from #DE to #CP and loads the IDT.
+///
+/// The handlers for #DF, NMI and #MC run on their own IST stacks, so the GDT with the TSS has to
+/// be loaded first.
+pub fn init() {
+    let mut idt = idt::IDT.lock();
+    for vector in EXCEPTIONS {
+        // the stubs handle the error code of their vector themselves
+        let entry = unsafe { idt.set_raw_handler(vector, trap::stub_addr(vector)) };
+        match vector {
+            // traps resume after the instruction, interrupts may arrive while they are handled
+            vector::DEBUG | vector::BREAKPOINT | vector::OVERFLOW => {
+                entry.set_gate_type(GateDescriptorType::Trap);
+            }
+            vector::DOUBLE_FAULT => {
+                entry.set_ist(Ist::from_tss_index(gdt::DOUBLE_FAULT_IST_INDEX));
+            }
+            vector::NMI => {
+                entry.set_ist(Ist::from_tss_index(gdt::NMI_IST_INDEX));
+            }
+            vector::MACHINE_CHECK => {
+                entry.set_ist(Ist::from_tss_index(gdt::MACHINE_CHECK_IST_INDEX));
+            }
+            _ => {}
+        }
+    }
+    drop(idt);
+    idt::load();
+}
+
+/// Handles the exception described by `frame`. Returns if the interrupted context can continue.
+pub fn handle(frame: &mut TrapFrame) {
+    let error_code = frame.error_code;
+    match frame.vector as u8 {
+        vector::DIVIDE_ERROR => fatal("#DE divide error", frame),
+        // #DB, #BP and #OF are traps, the saved rip already points past the instruction
+        vector::DEBUG => report("#DB debug", frame),
+        vector::NMI => report("NMI", frame),
+        vector::BREAKPOINT => report("#BP breakpoint", frame),
+        vector::OVERFLOW => report("#OF overflow", frame),
+        vector::BOUND_RANGE => fatal("#BR bound range exceeded", frame),
+        vector::INVALID_OPCODE => fatal("#UD invalid opcode", frame),
+        // nothing uses the x87/SSE state lazily yet, so this is never expected
+        vector::DEVICE_NOT_AVAILABLE => fatal("#NM device not available", frame),
+        vector::DOUBLE_FAULT => double_fault(frame),
+        vector::INVALID_TSS => {
+            fatal_with_err_code("#TS invalid TSS", SelectorErrorCode(error_code), frame)
+        }
+        vector::SEGMENT_NOT_PRESENT => fatal_with_err_code(
+            "#NP segment not present",
+            SelectorErrorCode(error_code),
+            frame,
+        ),
+        vector::STACK_FAULT => {
+            fatal_with_err_code("#SS stack fault", SelectorErrorCode(error_code), frame)
+        }
+        vector::GENERAL_PROTECTION => fatal_with_err_code(
+            "#GP general protection",
+            SelectorErrorCode(error_code),
+            frame,
+        ),
+        vector::PAGE_FAULT => page_fault(frame),
+        vector::X87_FLOATING_POINT => fatal("#MF x87 floating point", frame),
+        // the error code is always 0
+        vector::ALIGNMENT_CHECK => {
+            fatal_with_err_code("#AC alignment check", Hex(error_code), frame)
+        }
+        vector::MACHINE_CHECK => fatal("#MC machine check", frame),
+        vector::SIMD_FLOATING_POINT => fatal("#XF SIMD floating point", frame),
+        vector::VIRTUALIZATION => fatal("#VE virtualization", frame),
+        vector::CONTROL_PROTECTION => fatal_with_err_code(
+            "#CP control protection",
+            ControlProtectionErrorCode(error_code),
+            frame,
+        ),
+        vector::SECURITY => fatal_with_err_code("#SX security", Hex(error_code), frame),
+        _ => fatal("unexpected vector", frame),
+    }
+}
+
+/// Error code of the exceptions that refer to a segment selector (#TS, #NP, #SS, #GP).
+///
+/// For further information refer to [8.4.1 Selector-Error Code](https://www.amd.com/content/dam/amd/en/documents/processor-tech-docs/programmer-references/24593.pdf#page=283).
+#[derive(Clone, Copy)]
+pub struct SelectorErrorCode(pub u64);
+
+impl SelectorErrorCode {
+    /// The exception was caused by an event external to the program, e.g. a hardware interrupt.
+    pub fn external(&self) -> bool {
+        self.0.bit_range(0..1) == 1
+    }
+
+    pub fn table(&self) -> DescriptorTable {
+        if self.0.bit_range(1..2) == 1 {
+            DescriptorTable::Idt
+        } else if self.0.bit_range(2..3) == 1 {
+            DescriptorTable::Ldt
+        } else {
+            DescriptorTable::Gdt
+        }
+    }
+
+    pub fn index(&self) -> u64 {
+        self.0.bit_range(3..16)
+    }
+}
+
+impl fmt::Debug for SelectorErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 == 0 {
+            // the fault is not related to a selector
+            return write!(f, "0");
+        }
+        write!(f, "{:?} index {}", self.table(), self.index())?;
+        if self.external() {
+            write!(f, " (external)")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DescriptorTable {
+    Gdt,
+    Idt,
+    Ldt,
+}
+
+/// Error code of a control protection exception.
+///
+/// For further information refer to [8.2.21 #CP—Control-Protection Exception](https://www.amd.com/content/dam/amd/en/documents/processor-tech-docs/programmer-references/24593.pdf#page=282).
+#[derive(Clone, Copy)]
+pub struct ControlProtectionErrorCode(pub u64);
+
+impl fmt::Debug for ControlProtectionErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cause = match self.0.bit_range(0..15) {
+            1 => "near RET",
+            2 => "far RET/IRET",
+            3 => "missing ENDBRANCH",
+            4 => "RSTORSSP",
+            5 => "SETSSBSY",
+            _ => "unknown",
+        };
+        write!(f, "{:#x} ({cause})", self.0)?;
+        if self.0.bit_range(15..16) == 1 {
+            write!(f, " in enclave")?;
+        }
+        Ok(())
+    }
+}
+
+fn report(name: &str, frame: &TrapFrame) {
+    error!("EXCEPTION: {name} (vector {})", frame.vector);
+    klog::log_lines(Level::Error, module_path!(), frame);
+}
+
+/// Reports a fault that can't be recovered from yet and halts.
+fn fatal(name: &str, frame: &TrapFrame) -> ! {
+    report(name, frame);
+    backtrace::log_from(frame.stack_frame.instruction_pointer, frame.rbp);
+    halt();
+}
+
+fn fatal_with_err_code(name: &str, error_code: impl fmt::Debug, frame: &TrapFrame) -> ! {
+    error!("EXCEPTION: {name} (vector {})", frame.vector);
+    error!("error code: {error_code:?}");
+    klog::log_lines(Level::Error, module_path!(), frame);
+    backtrace::log_from(frame.stack_frame.instruction_pointer, frame.rbp);
+    halt();
+}
+
+struct Hex(u64);
+
+impl fmt::Debug for Hex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}", self.0)
+    }
+}
+
+fn double_fault(frame: &TrapFrame) -> ! {
+    // we run on our own IST stack, the faulting stack is likely unusable
+    let rsp = frame.stack_frame.stack_pointer;
+    let cr2 = frame.cr2;
+    report("#DF double fault", frame);
+    if cr2 <= rsp && rsp - cr2 < PageSize::KB4 as u64 {
+        error!("CR2 lies right below RSP, the kernel stack overflowed");
+    } else {
+        error!("a kernel stack overflow is likely");
+    }
+    backtrace::log_from(frame.stack_frame.instruction_pointer, frame.rbp);
+    halt();
+}
+
+/// The error for an access to a present page that the fault handlers couldn't resolve.
+fn protection_violation(addr: VirtAddr) -> Result<(), FaultError> {
+    let region = vmalloc::find_fault_region(addr)?;
+    Err(FaultError::AccessViolation(region))
+}
+
+fn page_fault(frame: &TrapFrame) {
+    let addr = frame.cr2;
+    let error_code = PageFaultErrorCode::from_bits_retain(frame.error_code);
+    let result = match VirtAddr::try_new(addr) {
+        Ok(virt) if !error_code.contains(PageFaultErrorCode::P) => unsafe {
+            vmalloc::handle_page_fault(
+                virt,
+                error_code.contains(PageFaultErrorCode::W),
+                error_code.contains(PageFaultErrorCode::I),
+            )
+        },
+        Ok(virt) if error_code.contains(PageFaultErrorCode::W) => {
+            match unsafe { cow::handle_write_fault(virt) } {
+                Ok(true) => Ok(()),
+                Ok(false) => protection_violation(virt),
+                Err(e) => Err(e),
+            }
+        }
+        Ok(virt) => protection_violation(virt),
+        Err(_) => Err(FaultError::NoRegion),
+    };
+    // a resolved fault simply restarts the faulting instruction
+    let Err(e) = result else {
+        return;
+    };
+
+    error!("EXCEPTION: #PF page fault at {addr:#x}");
+    error!(
+        "error code: {:#x} ({}, {}, {} mode{}{})",
+        error_code.bits(),
+        if error_code.contains(PageFaultErrorCode::P) {
+            "protection violation"
+        } else {
+            "not present"
+        },
+        if error_code.contains(PageFaultErrorCode::I) {
+            "instruction fetch"
+        } else if error_code.contains(PageFaultErrorCode::W) {
+            "write"
+        } else {
+            "read"
+        },
+        if error_code.contains(PageFaultErrorCode::U) {
+            "user"
+        } else {
+            "kernel"
+        },
+        if error_code.contains(PageFaultErrorCode::PK) {
+            ", protection key"
+        } else {
+            ""
+        },
+        if error_code.contains(PageFaultErrorCode::RSV) {
+            ", reserved bit set"
+        } else {
+            ""
+        },
+    );
+    match e {
+        FaultError::NoRegion => error!("address is not part of any region"),
+        FaultError::NotLazy(region) => error!("page of non lazy region missing: {region}"),
+        FaultError::AccessViolation(region) => error!("access violates region: {region}"),
+        FaultError::OutOfMemory(region) => error!("out of frames for region: {region}"),
+        FaultError::Map(region, e) => error!("mapping the page failed ({e:?}): {region}"),
+        FaultError::FramesLocked => {
+            error!("fault while the frame allocator is locked, it must not touch lazy memory")
+        }
+        FaultError::RegionsLocked => {
+            error!("fault while the vmalloc region list is locked, it must not touch lazy memory")
+        }
+    }
+    klog::log_lines(Level::Error, module_path!(), frame);
+    backtrace::log_from(frame.stack_frame.instruction_pointer, frame.rbp);
+    halt();
+}
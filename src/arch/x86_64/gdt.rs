@@ -1,6 +1,91 @@
 use core::arch::asm;
+use core::mem::size_of;
 use core::u16;
 
+use lazy_static::lazy_static;
+use yashima_core::mem::page::PageSize;
+
+use crate::mem::vmalloc;
+use crate::mem::vmalloc::VmPerms;
+
+pub const KERNEL_CODE_SELECTOR: SegmentSelector = SegmentSelector::new(1, Ring::Ring0);
+pub const KERNEL_DATA_SELECTOR: SegmentSelector = SegmentSelector::new(2, Ring::Ring0);
+// user data comes before user code, that's the order `sysret` expects
+pub const USER_DATA_SELECTOR: SegmentSelector = SegmentSelector::new(3, Ring::Ring3);
+pub const USER_CODE_SELECTOR: SegmentSelector = SegmentSelector::new(4, Ring::Ring3);
+pub const TSS_SELECTOR: SegmentSelector = SegmentSelector::new(5, Ring::Ring0);
+
+/// Indices into [`Tss::ist`] of the stacks for exceptions that can't trust the current stack.
+///
+/// A double fault is most likely caused by a kernel stack overflow, an NMI and a machine check
+/// can hit at any point, even in the middle of a stack switch.
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub const NMI_IST_INDEX: u16 = 1;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 2;
+
+const IST_STACK_SIZE: u64 = 4 * PageSize::KB4 as u64;
+
+lazy_static! {
+    static ref TSS: Tss = {
+        let mut tss = Tss::new();
+        tss.ist[DOUBLE_FAULT_IST_INDEX as usize] = allocate_ist_stack("#DF stack");
+        tss.ist[NMI_IST_INDEX as usize] = allocate_ist_stack("NMI stack");
+        tss.ist[MACHINE_CHECK_IST_INDEX as usize] = allocate_ist_stack("#MC stack");
+        tss
+    };
+    static ref GDT: Gdt = Gdt::new(&TSS);
+}
+
+/// Allocates an eagerly backed stack and returns its top. vmalloc keeps an unmapped guard page
+/// below it, so an overflow faults instead of running into the neighbouring region.
+fn allocate_ist_stack(name: &'static str) -> u64 {
+    // eager, a lazy stack would need the page fault handler to work on a broken stack
+    let bottom = vmalloc::vmalloc(name, IST_STACK_SIZE, VmPerms::WRITE)
+        .unwrap_or_else(|e| panic!("couldn't allocate {name}: {e:?}"));
+    (bottom + IST_STACK_SIZE).as_u64()
+}
+
+/// Loads the kernel [`Gdt`], reloads all segment registers with the new selectors and loads the
+/// task register with the [`Tss`].
+///
+/// Until this is called we run on the GDT limine left us with. The IST stacks are allocated from
+/// vmalloc, so the memory management has to be set up before.
+pub fn init() {
+    let gdt_pointer = GdtPointer {
+        limit: (size_of::<Gdt>() - 1) as u16,
+        base_adr: &*GDT as *const Gdt as *mut Gdt,
+    };
+    unsafe {
+        asm!("lgdt [{}]", in(reg) &gdt_pointer, options(readonly, nostack, preserves_flags));
+        // cs can't be written with mov, a far return pops the new cs together with the rip
+        asm!(
+            "push {cs}",
+            "lea {tmp}, [rip + 2f]",
+            "push {tmp}",
+            "retfq",
+            "2:",
+            cs = in(reg) KERNEL_CODE_SELECTOR.0 as u64,
+            tmp = lateout(reg) _,
+            options(preserves_flags),
+        );
+        asm!(
+            "mov ds, {0:x}",
+            "mov es, {0:x}",
+            "mov ss, {0:x}",
+            in(reg) KERNEL_DATA_SELECTOR.0,
+            options(nostack, preserves_flags),
+        );
+        // fs and gs are only used through their base MSRs
+        asm!(
+            "mov fs, {0:x}",
+            "mov gs, {0:x}",
+            in(reg) 0u16,
+            options(nostack, preserves_flags),
+        );
+        asm!("ltr {0:x}", in(reg) TSS_SELECTOR.0, options(nostack, preserves_flags));
+    }
+}
+
 // TODO robert refactor this at some point
 
 /// The GdtPointer represents the visible part required by the GDT-Register (GDTR) to
@@ -44,20 +129,41 @@ impl GdtPointer {
 }
 
 /// The Global Descriptor Table (Gdt) stores the CS, DS, ES, GS, FS, SS Segment Descriptors ([`SegmentDescriptor`])
-/// In long mode most of the segmentation and its features is disabled/ignored. That's why we only store the null-selector required by the
-/// processor at index 0, a singular code segment for the required code segment.
+/// In long mode most of the segmentation and its features is disabled/ignored. That's why we only
+/// store the null-selector required by the processor at index 0 and a code and data segment per
+/// privilege level.
 ///
-/// DS, SS, ES are ignored. I do not currently make use ou of the GS and FS.
+/// The last two entries hold the 16 byte system descriptor of the [`Tss`].
 ///
-// TODO add source and make sure we really onlly need a singular data segement for all the data segments
-// TODO should be aligned on an 8 byte boundary
-// TODO fix this description as its no longer true with limine bootloader
+/// | index | selector | descriptor   |
+/// |-------|----------|--------------|
+/// | 0     | 0x00     | null         |
+/// | 1     | 0x08     | kernel code  |
+/// | 2     | 0x10     | kernel data  |
+/// | 3     | 0x1b     | user data    |
+/// | 4     | 0x23     | user code    |
+/// | 5, 6  | 0x28     | tss          |
 #[derive(Clone, Copy, Debug)]
-#[repr(transparent)]
-#[allow(unused)]
+#[repr(C, align(8))]
 pub struct Gdt {
-    // we only need the null descriptor (first entry) and the cs and ds
-    pub entries: [SegmentDescriptor; 6],
+    pub entries: [SegmentDescriptor; 7],
+}
+
+impl Gdt {
+    pub fn new(tss: &'static Tss) -> Gdt {
+        let [tss_lower, tss_upper] = SegmentDescriptor::new_tss(tss);
+        Gdt {
+            entries: [
+                SegmentDescriptor::null(),
+                SegmentDescriptor::new_cs(Ring::Ring0, false),
+                SegmentDescriptor::new_ds(Ring::Ring0),
+                SegmentDescriptor::new_ds(Ring::Ring3),
+                SegmentDescriptor::new_cs(Ring::Ring3, false),
+                tss_lower,
+                tss_upper,
+            ],
+        }
+    }
 }
 
 /// A Segment Descriptor that exists inside the [`Gdt`].
@@ -80,6 +186,11 @@ pub struct SegmentDescriptor {
 }
 
 impl SegmentDescriptor {
+    /// The processor requires the first entry of the [`Gdt`] to be a null descriptor.
+    pub const fn null() -> SegmentDescriptor {
+        Self { lower: 0, upper: 0 }
+    }
+
     /// `dpl` descriptor privilige level
     /// `c` conforming bit
     // TODO move the comments of the variable stuff into proper docs. reference the rest as:
@@ -123,25 +234,20 @@ impl SegmentDescriptor {
             upper = upper | 1 << 10;
         }
 
+        // Code/Data (bit 11) and S (bit 12). The type is still checked when a selector is loaded
+        // into cs, so the descriptor has to identify itself as a code segment (bit 11 = 1) of the
+        // user segment kind (S = 1), as opposed to a system segment like the TSS.
+        upper = upper | 1 << 11 | 1 << 12;
+
         // all other fields are ignored in 64 bit long mode
         Self { lower, upper }
     }
 
-    pub fn get_from_cs_r() -> SegmentDescriptor {
-        // TODO load cs segment from the cs register
-
-        // init values will be overriden so they dont matter
-        let mut cs_descr = SegmentDescriptor::new_cs(Ring::Ring0, true);
-        unsafe {
-            asm!("mov [{}], cs", in(reg) &mut cs_descr as *mut SegmentDescriptor);
-        }
-        cs_descr
-    }
-
-    pub fn new_ds() -> SegmentDescriptor {
-        // TODO i actually think we dont need this at all
+    /// `dpl` descriptor privilige level. Has to match the privilege level of the code that loads
+    /// it into ss.
+    pub fn new_ds(dpl: Ring) -> SegmentDescriptor {
         // https://www.amd.com/content/dam/amd/en/documents/processor-tech-docs/programmer-references/24593.pdf#page=159
-        let mut lower = 0;
+        let lower = 0;
         let mut upper = 0;
 
         // Present (P) Bit. Bit 15 of the upper double-word. The segment-present bit indicates that the segment
@@ -150,18 +256,90 @@ impl SegmentDescriptor {
         // and is never altered by the processor.
         upper = upper | 1 << 15;
 
+        // Descriptor Privilege-Level (DPL) Field. Bits 14:13 of the upper double-word. Ignored for
+        // ds and es, but `iretq` to ring 3 checks it for ss.
+        upper = upper | (dpl as u32) << 13;
+
+        // S (bit 12) marks a code/data segment, Code/Data (bit 11) stays 0 for data. Writable (bit
+        // 9) is required for anything that is loaded into ss.
+        upper = upper | 1 << 12 | 1 << 9;
+
         // all other fields are ignored in 64 bit long mode
         Self { lower, upper }
     }
-    pub fn get_from_ds_r() -> SegmentDescriptor {
-        // TODO load ds segment from the ds register
-        core::unimplemented!("not yet implemented");
+
+    /// Creates the 16 byte system descriptor of a 64-bit TSS, which occupies two entries in the
+    /// [`Gdt`].
+    ///
+    /// For the layout refer to [4.8.3 System Descriptors](https://www.amd.com/content/dam/amd/en/documents/processor-tech-docs/programmer-references/24593.pdf#page=161).
+    pub fn new_tss(tss: &'static Tss) -> [SegmentDescriptor; 2] {
+        let base = tss as *const Tss as u64;
+        let limit = (size_of::<Tss>() - 1) as u64;
+
+        // segment limit 15:0 and base address 15:0
+        let lower = (limit & 0xffff) as u32 | ((base & 0xffff) as u32) << 16;
+
+        let mut upper = 0;
+        // base address 23:16
+        upper = upper | ((base >> 16) & 0xff) as u32;
+        // type 0b1001: available 64-bit TSS. S (bit 12) stays 0 for system segments.
+        upper = upper | 0b1001 << 8;
+        // present
+        upper = upper | 1 << 15;
+        // segment limit 19:16
+        upper = upper | (((limit >> 16) & 0xf) as u32) << 16;
+        // base address 31:24
+        upper = upper | (((base >> 24) & 0xff) as u32) << 24;
+
+        // the second entry holds base address 63:32, the rest is reserved
+        let high = SegmentDescriptor {
+            lower: (base >> 32) as u32,
+            upper: 0,
+        };
+        [SegmentDescriptor { lower, upper }, high]
+    }
+}
+
+/// The 64-bit Task State Segment.
+///
+/// Hardware task switching does not exist in long mode, the TSS only holds the stack pointers the
+/// processor switches to on privilege level changes (`rsp`) and for interrupts with an IST index
+/// (`ist`).
+///
+/// For the layout refer to [12.2.5 64-Bit Task State Segment](https://www.amd.com/content/dam/amd/en/documents/processor-tech-docs/programmer-references/24593.pdf#page=416).
+#[derive(Clone, Copy, Debug)]
+#[repr(C, packed)]
+pub struct Tss {
+    reserved_0: u32,
+    /// Stack pointers loaded when switching to ring 0-2.
+    pub rsp: [u64; 3],
+    reserved_1: u64,
+    /// Interrupt Stack Table. IST index n in a gate descriptor selects `ist[n - 1]`.
+    pub ist: [u64; 7],
+    reserved_2: u64,
+    reserved_3: u16,
+    /// Offset of the I/O permission bitmap. Pointing past the limit means there is none.
+    pub iomap_base: u16,
+}
+
+impl Tss {
+    pub fn new() -> Tss {
+        Tss {
+            reserved_0: 0,
+            rsp: [0; 3],
+            reserved_1: 0,
+            ist: [0; 7],
+            reserved_2: 0,
+            reserved_3: 0,
+            iomap_base: size_of::<Tss>() as u16,
+        }
     }
 }
 
 /// A lower privilege level is numerically higher.
 /// Ring0 is the highest privilege level and the lowest numerically.
 /// User Space is handled in Ring3. The kernel is handled in Ring0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Ring {
     Ring0 = 0b00,
     Ring1 = 0b01,
@@ -178,13 +356,44 @@ pub enum Ring {
 /// - `RPL` (Requested Privilege Level): Bits 0 to 1. Specifies the privilege level of the selector.
 /// - `TI` (Table Indicator): Bit 2. Indicates the table from which the segment is selected. A value of 0 selects the GDT, and a value of 1 selects the LDT. The LDT is generally not used in modern systems and is not used in this OS.
 /// - `Index`: Bits 3 to 15. Specifies the index into the GDT or LDT, identifying the specific segment as offset into the GDT.
-#[derive(Clone, Copy, Debug)]
-#[warn(dead_code)]
-pub struct SegmentSelector(u16);
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct SegmentSelector(pub u16);
 
 impl SegmentSelector {
-    pub fn new(index: u16, rpl: Ring) -> SegmentSelector {
+    pub const fn new(index: u16, rpl: Ring) -> SegmentSelector {
         // the TI bit is always 0
         SegmentSelector(index << 3 | rpl as u16)
     }
+
+    pub fn index(&self) -> u16 {
+        self.0 >> 3
+    }
+
+    pub fn rpl(&self) -> Ring {
+        match self.0 & 0b11 {
+            0b00 => Ring::Ring0,
+            0b01 => Ring::Ring1,
+            0b10 => Ring::Ring2,
+            _ => Ring::Ring3,
+        }
+    }
+
+    /// Reads the selector currently loaded into cs.
+    pub fn get_from_cs_r() -> SegmentSelector {
+        let selector: u16;
+        unsafe {
+            asm!("mov {:x}, cs", out(reg) selector, options(nomem, nostack, preserves_flags));
+        }
+        SegmentSelector(selector)
+    }
+
+    /// Reads the selector currently loaded into ds.
+    pub fn get_from_ds_r() -> SegmentSelector {
+        let selector: u16;
+        unsafe {
+            asm!("mov {:x}, ds", out(reg) selector, options(nomem, nostack, preserves_flags));
+        }
+        SegmentSelector(selector)
+    }
 }
@@ -1,80 +1,345 @@
-// use crate::arch::x86_64::gdt::{Ring, SegmentSelector};
-// 
-// // TODO i should be able to align this when allocating since the bootloader im using doesnt allocate it (i think)
-// // and if it does i can just replace it
-// #[repr(align(16))]
-// struct Idt([GateDescriptor]);
-// 
-// /// This struct encapsulates a 64-bit gate descriptor for long mode.
-// /// Gate Descriptors in Long mode are either Interrupt Gates or trap Gates. Task Gates are not supported.
-// ///
-// /// TODO add the fields here maybe? or just ascii art for the fields
-// ///
-// /// For more information refer to the Intel® 64 and IA-32 Architectures Software Developer's Manual, Combined Volumes 3A, 3B, 3C, and 3D: System Programming Guide, specifically on page 233.
-// /// alternative source: https://www.amd.com/content/dam/amd/en/documents/processor-tech-docs/programmer-references/24593.pdf#page=161
-// #[derive(Clone, Copy, Debug)]
-// #[repr(C, packed)]
-// pub struct GateDescriptor {
-//     // first part of the offset, low bytes 0-15
-//     offset_low: u16,
-//     // this should be a code segment which is used in long mode to verify privilege level
-//     selector: SegmentSelector,
-//     // contains: IST (interrupt stack table), some set bits, TYPE, DPL, P, etc.
-//     attributes: GateAttributes,
-//     // offset in the second word, middle bytes 16-31
-//     offset_middle: u16,
-//     // offset in the third word for long mode addresses, high bytes 32-63
-//     offset_high: u32,
-//     // explicitly reserved
-//     reserved: u32,
-// }
-// 
-// impl GateDescriptor {
-//     /// The 'offset' is the 64 bit address of the Interrupt Handler.
-//     /// The 'selector' should be a Code Segment Selector in the GDT.
-//     pub fn new(offset: u64, selector: SegmentSelector) -> GateDescriptor {
-//         let offset_low: u16 = (offset & 0xFFFF) as u16;
-//         let offset_middle: u16 = ((offset >> 16) & 0xFFFF) as u16;
-//         let offset_high: u32 = ((offset >> 32) & 0xFFFFFFFF) as u32;
-//         let attributes = GateAttributes::new();
-// 
-//         Self {
-//             offset_low,
-//             selector,
-//             attributes,
-//             offset_middle,
-//             offset_high,
-//             reserved: 0,
-//         }
-//     }
-// }
-// 
-// #[derive(Clone, Copy, Debug)]
-// #[repr(transparent)]
-// pub struct GateAttributes(u16);
-// 
-// impl GateAttributes {
-//     // amd reference
-//     // Setting the gate DPL=3 and interrupt-handler code-segment DPL=0 makes the
-//     // exception handler or interrupt handler reachable from any privilege level.
-//     pub fn new(ist: Ist, gate_type: GateDescriptorType, dpl: Ring) -> GateAttributes {
-//         // TODO implement this, figure out what the ist is
-//         // Bit 0-2 is the IST (interrupt stack table)
-//         // Bit 3-7 are 0's
-//         // Bit 8-11 are the TYPE
-//         // Bit 12 is 0
-//         // Bit 13-14 is the DPL (descriptor privilege level)
-//         // Bit P is present bit (probably set by the processor again and not by me)
-//         Self {}
-//     }
-// }
-// 
-// 
-// /// TODO Interrupt Stack Table
-// struct Ist {}
-// 
-// /// https://www.amd.com/content/dam/amd/en/documents/processor-tech-docs/programmer-references/24593.pdf#page=160
-// enum GateDescriptorType {
-//     Interrupt = 0b1110,
-//     Trap = 0b1111,
-// }
+use core::arch::asm;
+use core::fmt;
+use core::mem::size_of;
+
+use bitflags::bitflags;
+use spin::Mutex;
+use yashima_core::bit;
+
+use crate::arch::x86_64::gdt::{Ring, SegmentSelector, KERNEL_CODE_SELECTOR};
+
+/// The IDT of the kernel. Handlers are registered through [`Idt::set_handler`], the table is
+/// activated with [`load`].
+///
+/// Entries may still be changed after loading, the processor reads the gate descriptor on every
+/// interrupt.
+pub static IDT: Mutex<Idt> = Mutex::new(Idt::new());
+
+/// Loads [`IDT`] into the IDT-Register.
+pub fn load() {
+    let idt = IDT.lock();
+    let idt_pointer = IdtPointer {
+        limit: (size_of::<Idt>() - 1) as u16,
+        // the table lives inside a static, so the address stays valid after the guard is dropped
+        base_adr: &*idt as *const Idt as u64,
+    };
+    unsafe {
+        asm!("lidt [{}]", in(reg) &idt_pointer, options(readonly, nostack, preserves_flags));
+    }
+}
+
+/// Vector numbers of the architecturally defined exceptions.
+///
+/// For further information refer to [8.2 Vectors](https://www.amd.com/content/dam/amd/en/documents/processor-tech-docs/programmer-references/24593.pdf#page=276).
+pub mod vector {
+    pub const DIVIDE_ERROR: u8 = 0;
+    pub const DEBUG: u8 = 1;
+    pub const NMI: u8 = 2;
+    pub const BREAKPOINT: u8 = 3;
+    pub const OVERFLOW: u8 = 4;
+    pub const BOUND_RANGE: u8 = 5;
+    pub const INVALID_OPCODE: u8 = 6;
+    pub const DEVICE_NOT_AVAILABLE: u8 = 7;
+    pub const DOUBLE_FAULT: u8 = 8;
+    pub const INVALID_TSS: u8 = 10;
+    pub const SEGMENT_NOT_PRESENT: u8 = 11;
+    pub const STACK_FAULT: u8 = 12;
+    pub const GENERAL_PROTECTION: u8 = 13;
+    pub const PAGE_FAULT: u8 = 14;
+    pub const X87_FLOATING_POINT: u8 = 16;
+    pub const ALIGNMENT_CHECK: u8 = 17;
+    pub const MACHINE_CHECK: u8 = 18;
+    pub const SIMD_FLOATING_POINT: u8 = 19;
+    pub const VIRTUALIZATION: u8 = 20;
+    pub const CONTROL_PROTECTION: u8 = 21;
+    pub const HYPERVISOR_INJECTION: u8 = 28;
+    pub const VMM_COMMUNICATION: u8 = 29;
+    pub const SECURITY: u8 = 30;
+}
+
+/// The IDT-Register content loaded by `lidt`.
+#[derive(Clone, Copy, Debug)]
+#[repr(C, packed)]
+struct IdtPointer {
+    limit: u16,
+    base_adr: u64,
+}
+
+/// The Interrupt Descriptor Table with one [`GateDescriptor`] for each of the 256 vectors.
+///
+/// Vectors 0-31 are reserved for exceptions, see [`vector`].
+#[derive(Clone, Debug)]
+#[repr(C, align(16))]
+pub struct Idt {
+    entries: [GateDescriptor; 256],
+}
+
+impl Idt {
+    /// An IDT with only not present entries. Any interrupt raises a #NP which is itself not
+    /// present and ends in a triple fault.
+    pub const fn new() -> Idt {
+        Idt {
+            entries: [GateDescriptor::missing(); 256],
+        }
+    }
+
+    /// Installs `handler` as an interrupt gate for `vector`, callable from ring 0 only and on the
+    /// current stack. The returned descriptor can be used to change these defaults.
+    ///
+    /// The signature of `handler` has to match the vector: exceptions that push an error code
+    /// need one of the handler types taking an error code.
+    pub fn set_handler<H: Handler>(&mut self, vector: u8, handler: H) -> &mut GateDescriptor {
+        unsafe { self.set_raw_handler(vector, handler.addr()) }
+    }
+
+    /// Like [`Idt::set_handler`], but for an entry point written in assembly.
+    ///
+    /// # Safety
+    ///
+    /// `addr` has to point to code that ends with an `iretq` and handles the error code of the
+    /// vector, if there is one.
+    pub unsafe fn set_raw_handler(&mut self, vector: u8, addr: u64) -> &mut GateDescriptor {
+        let entry = &mut self.entries[vector as usize];
+        *entry = GateDescriptor::new(addr, KERNEL_CODE_SELECTOR);
+        entry
+    }
+
+    /// Marks the entry for `vector` as not present again.
+    pub fn remove_handler(&mut self, vector: u8) {
+        self.entries[vector as usize] = GateDescriptor::missing();
+    }
+
+    pub fn entry(&self, vector: u8) -> &GateDescriptor {
+        &self.entries[vector as usize]
+    }
+}
+
+/// The values the processor pushes onto the stack before calling an interrupt handler.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct InterruptStackFrame {
+    pub instruction_pointer: u64,
+    pub code_segment: u64,
+    pub cpu_flags: u64,
+    pub stack_pointer: u64,
+    pub stack_segment: u64,
+}
+
+impl fmt::Debug for InterruptStackFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        struct Hex(u64);
+        impl fmt::Debug for Hex {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{:#x}", self.0)
+            }
+        }
+        f.debug_struct("InterruptStackFrame")
+            .field("instruction_pointer", &Hex(self.instruction_pointer))
+            .field("code_segment", &Hex(self.code_segment))
+            .field("cpu_flags", &Hex(self.cpu_flags))
+            .field("stack_pointer", &Hex(self.stack_pointer))
+            .field("stack_segment", &Hex(self.stack_segment))
+            .finish()
+    }
+}
+
+bitflags! {
+    /// Error code pushed by a page fault.
+    ///
+    /// For further information refer to [8.4.2 Page-Fault Error Code](https://www.amd.com/content/dam/amd/en/documents/processor-tech-docs/programmer-references/24593.pdf#page=284).
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[repr(transparent)]
+    pub struct PageFaultErrorCode: u64 {
+        // Present
+        // 0 if the fault was caused by a not present page, 1 if it was a protection violation.
+        const P = bit!(0);
+        // Write
+        // The access causing the fault was a write.
+        const W = bit!(1);
+        // User
+        // The access was done in user mode (CPL 3).
+        const U = bit!(2);
+        // Reserved
+        // A reserved bit in one of the paging structures was set.
+        const RSV = bit!(3);
+        // Instruction Fetch
+        // The access was an instruction fetch (only reported with NX or SMEP enabled).
+        const I = bit!(4);
+        // Protection Key
+        // The access violated the protection key rights.
+        const PK = bit!(5);
+        // Shadow Stack
+        // The access was a shadow stack access.
+        const SS = bit!(6);
+        // Reverse Map Table
+        // The fault was caused by a RMP check (SEV-SNP).
+        const RMP = bit!(31);
+    }
+}
+
+pub type HandlerFunc = extern "x86-interrupt" fn(InterruptStackFrame);
+pub type HandlerFuncWithErrCode = extern "x86-interrupt" fn(InterruptStackFrame, u64);
+pub type DivergingHandlerFunc = extern "x86-interrupt" fn(InterruptStackFrame) -> !;
+pub type DivergingHandlerFuncWithErrCode =
+    extern "x86-interrupt" fn(InterruptStackFrame, u64) -> !;
+pub type PageFaultHandlerFunc = extern "x86-interrupt" fn(InterruptStackFrame, PageFaultErrorCode);
+
+/// Anything that can be installed into a [`GateDescriptor`].
+pub trait Handler {
+    fn addr(self) -> u64;
+}
+
+macro_rules! impl_handler {
+    ($($handler:ty),*) => {
+        $(impl Handler for $handler {
+            fn addr(self) -> u64 {
+                self as u64
+            }
+        })*
+    };
+}
+
+impl_handler!(
+    HandlerFunc,
+    HandlerFuncWithErrCode,
+    DivergingHandlerFunc,
+    DivergingHandlerFuncWithErrCode,
+    PageFaultHandlerFunc
+);
+
+/// This struct encapsulates a 64-bit gate descriptor for long mode.
+/// Gate Descriptors in Long mode are either Interrupt Gates or trap Gates. Task Gates are not
+/// supported.
+///
+/// ```text
+///  127                        96 95                         64
+/// +----------------------------+----------------------------+
+/// |          reserved          |        offset 63:32        |
+/// +----------------------------+----------------------------+
+///  63           48 47         32 31           16 15          0
+/// +---------------+-------------+---------------+------------+
+/// | offset 31:16  | attributes  |   selector    | offset 15:0|
+/// +---------------+-------------+---------------+------------+
+/// ```
+///
+/// For more information refer to the Intel® 64 and IA-32 Architectures Software Developer's Manual, Combined Volumes 3A, 3B, 3C, and 3D: System Programming Guide, specifically on page 233.
+/// alternative source: https://www.amd.com/content/dam/amd/en/documents/processor-tech-docs/programmer-references/24593.pdf#page=161
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct GateDescriptor {
+    // first part of the offset, low bytes 0-15
+    offset_low: u16,
+    // this should be a code segment which is used in long mode to verify privilege level
+    selector: SegmentSelector,
+    // contains: IST (interrupt stack table), some set bits, TYPE, DPL, P, etc.
+    attributes: GateAttributes,
+    // offset in the second word, middle bytes 16-31
+    offset_middle: u16,
+    // offset in the third word for long mode addresses, high bytes 32-63
+    offset_high: u32,
+    // explicitly reserved
+    reserved: u32,
+}
+
+impl GateDescriptor {
+    /// The 'offset' is the 64 bit address of the Interrupt Handler.
+    /// The 'selector' should be a Code Segment Selector in the GDT.
+    pub fn new(offset: u64, selector: SegmentSelector) -> GateDescriptor {
+        let offset_low: u16 = (offset & 0xFFFF) as u16;
+        let offset_middle: u16 = ((offset >> 16) & 0xFFFF) as u16;
+        let offset_high: u32 = ((offset >> 32) & 0xFFFFFFFF) as u32;
+        let attributes = GateAttributes::new(Ist::NONE, GateDescriptorType::Interrupt, Ring::Ring0);
+
+        Self {
+            offset_low,
+            selector,
+            attributes,
+            offset_middle,
+            offset_high,
+            reserved: 0,
+        }
+    }
+
+    /// A not present entry.
+    pub const fn missing() -> GateDescriptor {
+        Self {
+            offset_low: 0,
+            selector: SegmentSelector(0),
+            attributes: GateAttributes(0),
+            offset_middle: 0,
+            offset_high: 0,
+            reserved: 0,
+        }
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset_low as u64 | (self.offset_middle as u64) << 16 | (self.offset_high as u64) << 32
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.attributes.0 & bit!(15) != 0
+    }
+
+    /// Switches to the stack in the given IST slot of the TSS when the gate is taken.
+    pub fn set_ist(&mut self, ist: Ist) -> &mut GateDescriptor {
+        self.attributes.0 = (self.attributes.0 & !0b111) | ist.0 as u16;
+        self
+    }
+
+    pub fn set_gate_type(&mut self, gate_type: GateDescriptorType) -> &mut GateDescriptor {
+        self.attributes.0 = (self.attributes.0 & !(0b1111 << 8)) | (gate_type as u16) << 8;
+        self
+    }
+
+    /// The lowest privilege level that may invoke the gate with an `int n` instruction.
+    /// Exceptions and hardware interrupts ignore the DPL.
+    pub fn set_dpl(&mut self, dpl: Ring) -> &mut GateDescriptor {
+        self.attributes.0 = (self.attributes.0 & !(0b11 << 13)) | (dpl as u16) << 13;
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+#[repr(transparent)]
+pub struct GateAttributes(u16);
+
+impl GateAttributes {
+    // amd reference
+    // Setting the gate DPL=3 and interrupt-handler code-segment DPL=0 makes the
+    // exception handler or interrupt handler reachable from any privilege level.
+    pub fn new(ist: Ist, gate_type: GateDescriptorType, dpl: Ring) -> GateAttributes {
+        // Bit 0-2 is the IST (interrupt stack table)
+        // Bit 3-7 are 0's
+        // Bit 8-11 are the TYPE
+        // Bit 12 is 0
+        // Bit 13-14 is the DPL (descriptor privilege level)
+        // Bit 15 is the present bit
+        Self(ist.0 as u16 | (gate_type as u16) << 8 | (dpl as u16) << 13 | bit!(15))
+    }
+}
+
+/// Interrupt Stack Table index of a gate.
+///
+/// 0 keeps the current stack (or switches to `rsp0` of the TSS on a privilege change), 1-7 select
+/// one of the stacks in [`crate::arch::x86_64::gdt::Tss::ist`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ist(u8);
+
+impl Ist {
+    pub const NONE: Ist = Ist(0);
+
+    /// The IST entry for the stack at `index` in the TSS' ist array.
+    pub const fn from_tss_index(index: u16) -> Ist {
+        assert!(index < 7, "the TSS only holds 7 IST stacks");
+        Ist(index as u8 + 1)
+    }
+}
+
+/// https://www.amd.com/content/dam/amd/en/documents/processor-tech-docs/programmer-references/24593.pdf#page=160
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GateDescriptorType {
+    /// Clears IF, so no further maskable interrupts arrive until the handler returns.
+    Interrupt = 0b1110,
+    /// Leaves IF untouched.
+    Trap = 0b1111,
+}
@@ -0,0 +1,220 @@
+// The I/O APICs route the interrupt lines of the devices, numbered as global system interrupts
+// (GSIs) across all of them, to the local APICs. The MADT tells where they are and how the ISA
+// IRQs are wired to them. For the registers refer to the 82093AA datasheet:
+// https://pdos.csail.mit.edu/6.828/2018/readings/ia32/ioapic.pdf
+
+use spin::{Mutex, Once};
+use yashima_core::acpi::madt::{Madt, Polarity, TriggerMode};
+use yashima_core::bit;
+
+use crate::mem::mmio::{ioremap, CacheType, MmioRegion};
+use crate::{acpi, info, warn};
+
+// the registers are reached indirectly, through a select and a window register
+const IOREGSEL: usize = 0x00;
+const IOWIN: usize = 0x10;
+const MMIO_SIZE: usize = 0x20;
+
+const REG_VERSION: u32 = 0x01;
+/// Each redirection entry takes two registers, the low half first.
+const REG_REDIRECTION: u32 = 0x10;
+
+// redirection entries, delivery mode fixed and physical destination mode are both 0
+const ACTIVE_LOW: u64 = bit!(13);
+const LEVEL_TRIGGERED: u64 = bit!(15);
+const MASKED: u64 = bit!(16);
+const DESTINATION_SHIFT: u64 = 56;
+
+/// GSIs below are ISA IRQs unless an override moves them.
+const ISA_IRQ_COUNT: u32 = 16;
+const MAX_IOAPICS: usize = 16;
+
+static IOAPICS: Once<IoApicList> = Once::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoApicError {
+    /// There is no MADT, or it lists no IOAPIC.
+    NotPresent,
+    /// No IOAPIC handles the GSI.
+    NoSuchGsi(u32),
+    /// Physical destination mode only reaches the local APICs with an 8 bit ID.
+    UnreachableCpu(u32),
+}
+
+struct IoApic {
+    id: u8,
+    gsi_base: u32,
+    /// Number of redirection entries, the IOAPIC handles `gsi_base..gsi_base + entries`.
+    entries: u32,
+    /// Selecting a register and accessing it has to happen in one go.
+    regs: Mutex<MmioRegion>,
+}
+
+struct IoApicList {
+    ioapics: [Option<IoApic>; MAX_IOAPICS],
+    len: usize,
+}
+
+impl IoApicList {
+    fn iter(&self) -> impl Iterator<Item = &IoApic> {
+        self.ioapics[..self.len].iter().flatten()
+    }
+}
+
+impl IoApic {
+    fn read(regs: &MmioRegion, reg: u32) -> u32 {
+        regs.write32(IOREGSEL, reg);
+        regs.read32(IOWIN)
+    }
+
+    fn write(regs: &MmioRegion, reg: u32, value: u32) {
+        regs.write32(IOREGSEL, reg);
+        regs.write32(IOWIN, value);
+    }
+
+    fn handles(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.entries).contains(&gsi)
+    }
+
+    fn read_entry(&self, gsi: u32) -> u64 {
+        let reg = REG_REDIRECTION + (gsi - self.gsi_base) * 2;
+        let regs = self.regs.lock();
+        Self::read(&regs, reg) as u64 | (Self::read(&regs, reg + 1) as u64) << 32
+    }
+
+    fn write_entry(&self, gsi: u32, entry: u64) {
+        let reg = REG_REDIRECTION + (gsi - self.gsi_base) * 2;
+        let regs = self.regs.lock();
+        // mask the entry first, so it never fires with half of the new settings
+        Self::write(&regs, reg, MASKED as u32);
+        Self::write(&regs, reg + 1, (entry >> 32) as u32);
+        Self::write(&regs, reg, entry as u32);
+    }
+}
+
+/// Maps every IOAPIC of the MADT and masks all of their entries.
+///
+/// Needs the ACPI tables and, for the mapping, the frame allocator. Until the IOAPICs are set
+/// up, the device interrupts stay with the legacy PICs.
+pub fn init() -> Result<(), IoApicError> {
+    let madt = madt().ok_or(IoApicError::NotPresent)?;
+    if madt.io_apics.is_empty() {
+        return Err(IoApicError::NotPresent);
+    }
+
+    let mut ioapics = IoApicList {
+        ioapics: [const { None }; MAX_IOAPICS],
+        len: 0,
+    };
+    for entry in &madt.io_apics {
+        if ioapics.len == MAX_IOAPICS {
+            warn!(
+                "ignoring ioapic {}, only {MAX_IOAPICS} are supported",
+                entry.id
+            );
+            continue;
+        }
+        let regs = unsafe { ioremap(entry.address as u64, MMIO_SIZE, CacheType::Uncacheable) };
+        let version = IoApic::read(&regs, REG_VERSION);
+        // bits 16-23 hold the index of the last entry
+        let entries = (version >> 16 & 0xff) + 1;
+        let ioapic = IoApic {
+            id: entry.id,
+            gsi_base: entry.gsi_base,
+            entries,
+            regs: Mutex::new(regs),
+        };
+        for gsi in ioapic.gsi_base..ioapic.gsi_base + entries {
+            ioapic.write_entry(gsi, MASKED);
+        }
+        info!(
+            "ioapic {} at {:#x}, version {:#x}, gsi {}-{}",
+            ioapic.id,
+            entry.address,
+            version & 0xff,
+            ioapic.gsi_base,
+            ioapic.gsi_base + entries - 1
+        );
+        ioapics.ioapics[ioapics.len] = Some(ioapic);
+        ioapics.len += 1;
+    }
+    for o in &madt.overrides {
+        info!(
+            "isa irq {} -> gsi {}, {:?} {:?}",
+            o.irq, o.gsi, o.polarity, o.trigger_mode
+        );
+    }
+    IOAPICS.call_once(|| ioapics);
+    Ok(())
+}
+
+pub fn is_initialized() -> bool {
+    IOAPICS.get().is_some()
+}
+
+/// The GSI the ISA interrupt `irq` arrives on. Without an override they are identity mapped.
+pub fn isa_irq_to_gsi(irq: u8) -> u32 {
+    match madt().and_then(|madt| madt.isa_override(irq)) {
+        Some(o) => o.gsi,
+        None => irq as u32,
+    }
+}
+
+/// Delivers `gsi` as `vector` to the local APIC with the ID `cpu` and unmasks it.
+///
+/// Polarity and trigger mode come from the MADT overrides. GSIs without an override keep the
+/// ISA defaults (active high, edge triggered) if they belong to an ISA IRQ, and the PCI defaults
+/// (active low, level triggered) otherwise.
+pub fn route_irq(gsi: u32, vector: u8, cpu: u32) -> Result<(), IoApicError> {
+    let ioapic = find(gsi)?;
+    if cpu > 0xff {
+        return Err(IoApicError::UnreachableCpu(cpu));
+    }
+    let entry = (cpu as u64) << DESTINATION_SHIFT | line_config(gsi) | vector as u64;
+    ioapic.write_entry(gsi, entry);
+    Ok(())
+}
+
+/// Stops `gsi` from being delivered.
+pub fn mask(gsi: u32) -> Result<(), IoApicError> {
+    let ioapic = find(gsi)?;
+    ioapic.write_entry(gsi, ioapic.read_entry(gsi) | MASKED);
+    Ok(())
+}
+
+/// Lets `gsi` through again, with the settings of the last [`route_irq`].
+pub fn unmask(gsi: u32) -> Result<(), IoApicError> {
+    let ioapic = find(gsi)?;
+    ioapic.write_entry(gsi, ioapic.read_entry(gsi) & !MASKED);
+    Ok(())
+}
+
+fn find(gsi: u32) -> Result<&'static IoApic, IoApicError> {
+    IOAPICS
+        .get()
+        .and_then(|ioapics| ioapics.iter().find(|ioapic| ioapic.handles(gsi)))
+        .ok_or(IoApicError::NoSuchGsi(gsi))
+}
+
+/// Polarity and trigger mode bits of the redirection entry of `gsi`.
+fn line_config(gsi: u32) -> u64 {
+    let isa_override = madt().and_then(|madt| madt.overrides.iter().find(|o| o.gsi == gsi));
+    let (polarity, trigger_mode) = match isa_override {
+        // conforming to the bus ends up as active high and edge triggered, the ISA defaults
+        Some(o) => (o.polarity, o.trigger_mode),
+        None if gsi < ISA_IRQ_COUNT => (Polarity::ActiveHigh, TriggerMode::Edge),
+        None => (Polarity::ActiveLow, TriggerMode::Level),
+    };
+    let mut config = 0;
+    if polarity == Polarity::ActiveLow {
+        config |= ACTIVE_LOW;
+    }
+    if trigger_mode == TriggerMode::Level {
+        config |= LEVEL_TRIGGERED;
+    }
+    config
+}
+
+fn madt() -> Option<&'static Madt> {
+    acpi::tables()?.madt.as_ref()
+}
@@ -0,0 +1,286 @@
+// The interrupt lines of devices and the handlers drivers register for them. Lines are numbered
+// as GSIs. With an IOAPIC every line gets a vector allocated when the first handler is
+// requested. With only the legacy PICs the GSIs 0-15 are their lines, which have fixed vectors.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+
+use crate::arch::x86_64::ioapic::IoApicError;
+use crate::arch::x86_64::{apic, idt, ioapic, pic, trap, without_interrupts};
+use crate::{print, println, trace, warn};
+
+/// Handles an interrupt of the line, `context` is the value passed to [`request_irq`].
+pub type Handler = fn(context: usize) -> IrqReturn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqReturn {
+    Handled,
+    /// The device of the handler didn't raise the interrupt, another one on the shared line did.
+    NotMine,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    /// The PICs only have the lines 0-15.
+    InvalidIrq(u32),
+    /// Every vector for device interrupts is taken.
+    NoFreeVector,
+    Routing(IoApicError),
+    /// No handler with this context is registered for the line.
+    NotRegistered,
+    /// The line already has [`MAX_SHARED`] handlers.
+    NoFreeSlot,
+}
+
+/// Vectors below belong to the exceptions and the legacy PICs.
+const FIRST_VECTOR: u8 = pic::VECTOR_BASE + pic::IRQ_COUNT;
+/// Vectors from here on belong to the local APIC itself.
+const APIC_VECTORS: u8 = apic::TIMER_VECTOR;
+/// How many devices can share a line.
+pub const MAX_SHARED: usize = 8;
+
+static LINES: Mutex<[Option<Line>; 256]> = Mutex::new([const { None }; 256]);
+static COUNTS: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+
+/// A line and the handlers of all devices sharing it.
+struct Line {
+    gsi: u32,
+    actions: [Option<Action>; MAX_SHARED],
+    len: usize,
+}
+
+#[derive(Clone, Copy)]
+struct Action {
+    name: &'static str,
+    handler: Handler,
+    context: usize,
+}
+
+impl Line {
+    fn new(gsi: u32) -> Self {
+        Line {
+            gsi,
+            actions: [None; MAX_SHARED],
+            len: 0,
+        }
+    }
+
+    fn actions(&self) -> impl Iterator<Item = &Action> {
+        self.actions[..self.len].iter().flatten()
+    }
+
+    fn add(&mut self, action: Action) -> Result<(), IrqError> {
+        if self.len == MAX_SHARED {
+            return Err(IrqError::NoFreeSlot);
+        }
+        self.actions[self.len] = Some(action);
+        self.len += 1;
+        Ok(())
+    }
+
+    fn remove(&mut self, context: usize) -> Result<(), IrqError> {
+        let index = self
+            .actions()
+            .position(|action| action.context == context)
+            .ok_or(IrqError::NotRegistered)?;
+        self.actions.copy_within(index + 1..self.len, index);
+        self.len -= 1;
+        self.actions[self.len] = None;
+        Ok(())
+    }
+}
+
+/// The GSI of the ISA interrupt `irq`, as [`request_irq`] expects it.
+pub fn isa_irq(irq: u8) -> u32 {
+    if ioapic::is_initialized() {
+        ioapic::isa_irq_to_gsi(irq)
+    } else {
+        irq as u32
+    }
+}
+
+/// Registers `handler` for the line `gsi` and lets the line through. Returns the vector the
+/// line is delivered on.
+///
+/// A line can be shared by several devices, their handlers are called one after the other. The
+/// handlers run with interrupts disabled and must not request or free lines themselves.
+pub fn request_irq(
+    gsi: u32,
+    name: &'static str,
+    handler: Handler,
+    context: usize,
+) -> Result<u8, IrqError> {
+    let action = Action {
+        name,
+        handler,
+        context,
+    };
+    // the dispatcher takes the lock in the interrupt handler
+    without_interrupts(|| {
+        let mut lines = LINES.lock();
+        if let Some(vector) = find_line(&lines, gsi) {
+            lines[vector as usize].as_mut().unwrap().add(action)?;
+            return Ok(vector);
+        }
+
+        let vector = if ioapic::is_initialized() {
+            (FIRST_VECTOR..APIC_VECTORS)
+                .find(|vector| lines[*vector as usize].is_none())
+                .ok_or(IrqError::NoFreeVector)?
+        } else if gsi < pic::IRQ_COUNT as u32 {
+            pic::VECTOR_BASE + gsi as u8
+        } else {
+            return Err(IrqError::InvalidIrq(gsi));
+        };
+        let mut line = Line::new(gsi);
+        line.add(action)?;
+        lines[vector as usize] = Some(line);
+
+        if ioapic::is_initialized() {
+            unsafe {
+                idt::IDT
+                    .lock()
+                    .set_raw_handler(vector, trap::stub_addr(vector))
+            };
+            if let Err(e) = ioapic::route_irq(gsi, vector, apic::local().id()) {
+                lines[vector as usize] = None;
+                return Err(IrqError::Routing(e));
+            }
+        } else {
+            // `pic::init` installed the stubs of its vectors
+            pic::unmask(gsi as u8);
+        }
+        Ok(vector)
+    })
+}
+
+/// Removes the handler registered with `context` from the line `gsi`. The line is masked once
+/// its last handler is gone.
+pub fn free_irq(gsi: u32, context: usize) -> Result<(), IrqError> {
+    without_interrupts(|| {
+        let mut lines = LINES.lock();
+        let vector = find_line(&lines, gsi).ok_or(IrqError::NotRegistered)? as usize;
+        let line = lines[vector].as_mut().unwrap();
+        line.remove(context)?;
+
+        if line.len == 0 {
+            // the line goes even if masking fails, it could never be freed again otherwise
+            lines[vector] = None;
+            if ioapic::is_initialized() {
+                ioapic::mask(gsi).map_err(IrqError::Routing)?;
+            } else {
+                pic::mask(gsi as u8);
+            }
+        }
+        Ok(())
+    })
+}
+
+fn find_line(lines: &[Option<Line>; 256], gsi: u32) -> Option<u8> {
+    lines
+        .iter()
+        .position(|line| line.as_ref().is_some_and(|line| line.gsi == gsi))
+        .map(|vector| vector as u8)
+}
+
+/// Counts an interrupt on `vector`, called for every vector that arrives.
+pub fn count(vector: u8) {
+    COUNTS[vector as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Calls the handlers registered for `vector`. Returns false if there are none.
+pub fn dispatch(vector: u8) -> bool {
+    let lines = LINES.lock();
+    let Some(line) = &lines[vector as usize] else {
+        return false;
+    };
+    let mut handled = false;
+    for action in line.actions() {
+        handled |= (action.handler)(action.context) == IrqReturn::Handled;
+    }
+    if !handled {
+        trace!(
+            "none of the handlers of irq {} took the interrupt",
+            line.gsi
+        );
+    }
+    true
+}
+
+/// Handles an interrupt on one of the vectors delivered by the IOAPIC.
+pub fn handle(vector: u8) {
+    if !dispatch(vector) {
+        warn!("interrupt on vector {vector} without a handler");
+    }
+    apic::local().end_of_interrupt();
+}
+
+/// Prints every vector that has a handler or has seen interrupts, like /proc/interrupts.
+pub fn print_interrupts() {
+    println!("vector        count  irq  handlers");
+    without_interrupts(|| {
+        let lines = LINES.lock();
+        for (vector, line) in lines.iter().enumerate() {
+            let count = COUNTS[vector].load(Ordering::Relaxed);
+            if count == 0 && line.is_none() {
+                continue;
+            }
+            print!("{vector:>6} {count:>12}");
+            match line {
+                Some(line) => {
+                    print!("  {:>3} ", line.gsi);
+                    for (i, action) in line.actions().enumerate() {
+                        print!("{}{}", if i == 0 { " " } else { ", " }, action.name);
+                    }
+                    println!();
+                }
+                None => println!("    -  {}", fixed_name(vector as u8)),
+            }
+        }
+    });
+}
+
+/// What the vectors without a line are used for.
+fn fixed_name(vector: u8) -> &'static str {
+    match vector {
+        0..=31 => "exception",
+        apic::TIMER_VECTOR => "apic timer",
+        apic::ERROR_VECTOR => "apic error",
+        apic::SPURIOUS_VECTOR => "apic spurious",
+        vector if vector >= pic::VECTOR_BASE && vector < FIRST_VECTOR => "pic",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn shared_lines() {
+        fn handler(_context: usize) -> IrqReturn {
+            IrqReturn::NotMine
+        }
+        // nothing sits on the parallel port in QEMU
+        let gsi = isa_irq(7);
+        let first = request_irq(gsi, "test a", handler, 1).unwrap();
+        let second = request_irq(gsi, "test b", handler, 2).unwrap();
+        assert_eq!(first, second);
+        for context in 3..=MAX_SHARED {
+            request_irq(gsi, "test c", handler, context).unwrap();
+        }
+        assert_eq!(
+            request_irq(gsi, "test d", handler, 0),
+            Err(IrqError::NoFreeSlot)
+        );
+        for context in 3..=MAX_SHARED {
+            free_irq(gsi, context).unwrap();
+        }
+
+        free_irq(gsi, 1).unwrap();
+        assert_eq!(free_irq(gsi, 1), Err(IrqError::NotRegistered));
+        free_irq(gsi, 2).unwrap();
+        assert!(LINES.lock()[first as usize].is_none());
+    }
+}
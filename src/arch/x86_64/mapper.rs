@@ -0,0 +1,190 @@
+use core::arch::asm;
+
+use yashima_core::bit;
+use yashima_core::mem::page::PageSize;
+use yashima_core::mem::PageFrameAllocator;
+use yashima_core::paging::{
+    PDEntry, PDFlags, PDPEntry, PDPFlags, PDPTable, PDTable, PML4Entry, PML4Flags, PML4Table,
+    PTEntry, PTFlags, PTable, PhysAddr, VirtAddr,
+};
+
+use crate::arch::x86_64::control::Cr3;
+
+/// Errors that can occur while creating a mapping in the active address space.
+#[derive(Debug, Clone, Copy)]
+pub enum MapError {
+    /// The frame allocator ran out of frames for a new paging structure.
+    FrameAllocationFailed,
+    /// The virtual address is already backed by a page.
+    AlreadyMapped,
+    /// The page walk ran into a 2Mb or 1Gb page. Splitting large pages is not supported.
+    LargePage,
+    /// The virtual address is not backed by a page.
+    NotMapped,
+}
+
+/// Maps the 4Kb page at `virt` to the physical frame `phys` in the currently active address
+/// space.
+///
+/// Missing paging structures are allocated from `frame_alloc` and zeroed. The intermediate
+/// entries are always present and writable, the access rights of the page are solely controlled
+/// by `flags` of the last level. The present bit is set regardless of `flags`.
+///
+/// # Safety
+///
+/// The caller has to make sure that the mapping does not alias memory in a way that breaks
+/// rust's reference rules and that `phys` is a frame that may be accessed.
+pub unsafe fn map_page(
+    virt: VirtAddr,
+    phys: PhysAddr,
+    flags: PTFlags,
+    frame_alloc: &mut impl PageFrameAllocator,
+) -> Result<(), MapError> {
+    let ptable = walk_create(virt, frame_alloc)?;
+    let entry = &mut ptable.entries[virt.pt_index()];
+    if entry.is_present() {
+        return Err(MapError::AlreadyMapped);
+    }
+    *entry = PTEntry::new(phys, flags | PTFlags::P);
+    flush_tlb(virt);
+    Ok(())
+}
+
+/// Removes the mapping of the 4Kb page at `virt` and returns the frame that backed it.
+///
+/// Paging structures that become empty are not freed.
+///
+/// # Safety
+///
+/// No references into the unmapped page may be alive.
+pub unsafe fn unmap_page(virt: VirtAddr) -> Option<PhysAddr> {
+    let ptable = walk(virt)?;
+    let entry = &mut ptable.entries[virt.pt_index()];
+    if !entry.is_present() {
+        return None;
+    }
+    let phys = entry.get_phys_addr();
+    *entry = PTEntry(0);
+    flush_tlb(virt);
+    Some(phys)
+}
+
+/// Returns the physical address `virt` is mapped to, if it is mapped by a 4Kb page.
+pub fn translate(virt: VirtAddr) -> Option<PhysAddr> {
+    let ptable = unsafe { walk(virt)? };
+    let entry = &ptable.entries[virt.pt_index()];
+    if !entry.is_present() {
+        return None;
+    }
+    Some(entry.get_phys_addr() + virt.page_offset())
+}
+
+/// Checks whether `virt` can be accessed without faulting, no matter the page size mapping it.
+pub fn is_mapped(virt: VirtAddr) -> bool {
+    unsafe {
+        let pml4 = PhysAddr::new(Cr3::read_from().get_base_addr()).as_hhdm_ref::<PML4Table>();
+        let pml4_entry = &pml4.entries[virt.pml4_index()];
+        if !pml4_entry.is_present() {
+            return false;
+        }
+        let pdp_table = pml4_entry.get_phys_addr().as_hhdm_ref::<PDPTable>();
+        let pdp_entry = &pdp_table.entries[virt.pdp_index()];
+        if !pdp_entry.is_present() || pdp_entry.0 & bit!(7) != 0 {
+            return pdp_entry.is_present();
+        }
+        let pd_table = pdp_entry.get_phys_addr().as_hhdm_ref::<PDTable>();
+        let pd_entry = &pd_table.entries[virt.pd_index()];
+        if !pd_entry.is_present() || pd_entry.maps_large_page() {
+            return pd_entry.is_present();
+        }
+        let ptable = pd_entry.get_phys_addr().as_hhdm_ref::<PTable>();
+        ptable.entries[virt.pt_index()].is_present()
+    }
+}
+
+/// Returns the page table entry that maps the 4Kb page at `virt`, if the paging structures down
+/// to it exist. The entry itself may be not present.
+///
+/// # Safety
+///
+/// The entry may not be aliased. Changes to it require a [`flush_tlb`].
+pub unsafe fn entry_mut(virt: VirtAddr) -> Option<&'static mut PTEntry> {
+    let ptable = walk(virt)?;
+    Some(&mut ptable.entries[virt.pt_index()])
+}
+
+/// Invalidates the TLB entry of the page containing `virt`.
+pub fn flush_tlb(virt: VirtAddr) {
+    unsafe {
+        asm!("invlpg [{}]", in(reg) virt.as_u64(), options(nostack, preserves_flags));
+    }
+}
+
+/// Walks down to the page table that maps `virt`, allocating every missing paging structure.
+unsafe fn walk_create(
+    virt: VirtAddr,
+    frame_alloc: &mut impl PageFrameAllocator,
+) -> Result<&'static mut PTable, MapError> {
+    let pml4 = PhysAddr::new(Cr3::read_from().get_base_addr()).as_hhdm_mut::<PML4Table>();
+
+    let pml4_entry = &mut pml4.entries[virt.pml4_index()];
+    if !pml4_entry.is_present() {
+        let frame = allocate_table(frame_alloc)?;
+        *pml4_entry = PML4Entry::new(frame, PML4Flags::P | PML4Flags::RW);
+    }
+    let pdp_table = pml4_entry.get_phys_addr().as_hhdm_mut::<PDPTable>();
+
+    let pdp_entry = &mut pdp_table.entries[virt.pdp_index()];
+    if !pdp_entry.is_present() {
+        let frame = allocate_table(frame_alloc)?;
+        *pdp_entry = PDPEntry::new(frame, PDPFlags::P | PDPFlags::RW);
+    } else if pdp_entry.0 & bit!(7) != 0 {
+        return Err(MapError::LargePage);
+    }
+    let pd_table = pdp_entry.get_phys_addr().as_hhdm_mut::<PDTable>();
+
+    let pd_entry = &mut pd_table.entries[virt.pd_index()];
+    if !pd_entry.is_present() {
+        let frame = allocate_table(frame_alloc)?;
+        *pd_entry = PDEntry::new(frame, PDFlags::P | PDFlags::RW);
+    } else if pd_entry.maps_large_page() {
+        return Err(MapError::LargePage);
+    }
+    Ok(pd_entry.get_phys_addr().as_hhdm_mut::<PTable>())
+}
+
+/// Walks down to the page table that maps `virt` without modifying any paging structure.
+unsafe fn walk(virt: VirtAddr) -> Option<&'static mut PTable> {
+    let pml4 = PhysAddr::new(Cr3::read_from().get_base_addr()).as_hhdm_mut::<PML4Table>();
+
+    let pml4_entry = &pml4.entries[virt.pml4_index()];
+    if !pml4_entry.is_present() {
+        return None;
+    }
+    let pdp_table = pml4_entry.get_phys_addr().as_hhdm_mut::<PDPTable>();
+
+    let pdp_entry = &pdp_table.entries[virt.pdp_index()];
+    if !pdp_entry.is_present() || pdp_entry.0 & bit!(7) != 0 {
+        return None;
+    }
+    let pd_table = pdp_entry.get_phys_addr().as_hhdm_mut::<PDTable>();
+
+    let pd_entry = &pd_table.entries[virt.pd_index()];
+    if !pd_entry.is_present() || pd_entry.maps_large_page() {
+        return None;
+    }
+    Some(pd_entry.get_phys_addr().as_hhdm_mut::<PTable>())
+}
+
+unsafe fn allocate_table(frame_alloc: &mut impl PageFrameAllocator) -> Result<PhysAddr, MapError> {
+    let frame = frame_alloc
+        .allocate_frame()
+        .ok_or(MapError::FrameAllocationFailed)?;
+    // a fresh paging structure must not contain any stale present entries
+    core::ptr::write_bytes(
+        frame.start.to_virt().as_mut_ptr::<u8>(),
+        0,
+        PageSize::KB4 as usize,
+    );
+    Ok(frame.start)
+}
@@ -1,7 +1,64 @@
+use core::arch::asm;
+
+pub mod apic;
 pub mod gdt;
-mod idt;
-pub mod paging;
+pub mod idt;
+pub mod ioapic;
+pub mod irq;
+pub mod port;
+pub mod qemu;
+pub mod mapper;
 pub mod cpuid;
 pub mod control;
+pub mod exceptions;
+pub mod msr;
+pub mod pic;
+pub mod trap;
+
+/// Stops the processor for good. Used when a fault leaves nothing to return to.
+pub fn halt() -> ! {
+    loop {
+        unsafe {
+            // an NMI still wakes up the processor, so we need to halt again
+            asm!("cli", "hlt", options(nomem, nostack));
+        }
+    }
+}
+
+/// Lets maskable interrupts through (sets RFLAGS.IF).
+pub fn enable_interrupts() {
+    unsafe { asm!("sti", options(nomem, nostack)) };
+}
+
+/// Holds back maskable interrupts (clears RFLAGS.IF).
+pub fn disable_interrupts() {
+    unsafe { asm!("cli", options(nomem, nostack)) };
+}
+
+/// Enables interrupts and halts until the next one arrives.
+///
+/// `sti` only takes effect after the following instruction, so an interrupt that is already
+/// pending can't slip in between and leave us halting with nothing left to wake us up.
+pub fn enable_interrupts_and_halt() {
+    unsafe { asm!("sti", "hlt", options(nomem, nostack)) };
+}
 
+pub fn interrupts_enabled() -> bool {
+    let rflags: u64;
+    unsafe { asm!("pushfq", "pop {}", out(reg) rflags, options(nomem, preserves_flags)) };
+    // IF is bit 9
+    rflags & (1 << 9) != 0
+}
 
+/// Runs `f` with maskable interrupts disabled and restores the previous state afterwards.
+pub fn without_interrupts<R>(f: impl FnOnce() -> R) -> R {
+    let enabled = interrupts_enabled();
+    if enabled {
+        disable_interrupts();
+    }
+    let result = f();
+    if enabled {
+        enable_interrupts();
+    }
+    result
+}
@@ -0,0 +1,45 @@
+use core::arch::asm;
+
+/// Base address and enable bits of the local APIC.
+pub const IA32_APIC_BASE: u32 = 0x1b;
+/// Absolute TSC value at which the APIC timer fires in TSC-deadline mode.
+pub const IA32_TSC_DEADLINE: u32 = 0x6e0;
+/// First MSR of the local APIC registers in x2APIC mode.
+pub const X2APIC_BASE: u32 = 0x800;
+
+/// Reads the model specific register `msr`.
+///
+/// # Safety
+///
+/// Reading an MSR that doesn't exist raises a #GP, some MSRs have side effects on read.
+pub unsafe fn rdmsr(msr: u32) -> u64 {
+    let (low, high): (u32, u32);
+    asm!(
+        "rdmsr",
+        in("ecx") msr,
+        out("eax") low,
+        out("edx") high,
+        options(nomem, nostack, preserves_flags)
+    );
+    (high as u64) << 32 | low as u64
+}
+
+/// Writes `value` to the model specific register `msr`.
+///
+/// # Safety
+///
+/// MSRs control all kinds of processor behaviour, writing one can break memory safety in
+/// arbitrary ways. Writing reserved bits or an MSR that doesn't exist raises a #GP.
+pub unsafe fn wrmsr(msr: u32, value: u64) {
+    let (low, high) = (value as u32, (value >> 32) as u32);
+    asm!("wrmsr", in("ecx") msr, in("eax") low, in("edx") high, options(nostack, preserves_flags));
+}
+
+/// Reads the time stamp counter.
+pub fn rdtsc() -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        asm!("rdtsc", out("eax") low, out("edx") high, options(nomem, nostack, preserves_flags))
+    };
+    (high as u64) << 32 | low as u64
+}
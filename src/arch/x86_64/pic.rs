@@ -0,0 +1,177 @@
+// The two cascaded 8259A PICs of the PC, our source of hardware interrupts until the APIC takes
+// over. For the command words refer to the 8259A datasheet:
+// https://pdos.csail.mit.edu/6.828/2018/readings/hardware/8259A.pdf
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use yashima_core::bit;
+
+use crate::arch::x86_64::port::{inb, outb};
+use crate::arch::x86_64::{idt, irq, trap, without_interrupts};
+use crate::{trace, warn};
+
+const MASTER_COMMAND: u16 = 0x20;
+const MASTER_DATA: u16 = 0x21;
+const SLAVE_COMMAND: u16 = 0xA0;
+const SLAVE_DATA: u16 = 0xA1;
+
+/// Vector of IRQ 0. The BIOS leaves the master at vector 8, right on top of the exceptions, so we
+/// move IRQ 0-7 to the vectors 32-39 and IRQ 8-15 to 40-47.
+pub const VECTOR_BASE: u8 = 32;
+pub const IRQ_COUNT: u8 = 16;
+
+/// The line of the master the slave is connected to.
+const CASCADE_IRQ: u8 = 2;
+
+// ICW1: start the initialization sequence, ICW4 follows
+const ICW1_INIT: u8 = bit!(4);
+const ICW1_ICW4: u8 = bit!(0);
+// ICW4: 8086 mode instead of MCS-80/85
+const ICW4_8086: u8 = bit!(0);
+// OCW2: non-specific end of interrupt
+const OCW2_EOI: u8 = bit!(5);
+// OCW3: the next read of the command port returns the in-service register
+const OCW3_READ_ISR: u8 = 0x0b;
+
+/// Unused port, writing it gives the PICs time to settle between two initialization words.
+const IO_WAIT_PORT: u16 = 0x80;
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+static SPURIOUS: AtomicU64 = AtomicU64::new(0);
+
+/// Remaps both PICs to [`VECTOR_BASE`], masks every line but the cascade and installs the entry
+/// stubs for the 16 vectors. The IDT has to be loaded already.
+pub fn init() {
+    unsafe {
+        outb(MASTER_COMMAND, ICW1_INIT | ICW1_ICW4);
+        io_wait();
+        outb(SLAVE_COMMAND, ICW1_INIT | ICW1_ICW4);
+        io_wait();
+        // ICW2: vector offsets
+        outb(MASTER_DATA, VECTOR_BASE);
+        io_wait();
+        outb(SLAVE_DATA, VECTOR_BASE + 8);
+        io_wait();
+        // ICW3: the master takes a bit mask of its slave lines, the slave its cascade identity
+        outb(MASTER_DATA, bit!(CASCADE_IRQ));
+        io_wait();
+        outb(SLAVE_DATA, CASCADE_IRQ);
+        io_wait();
+        outb(MASTER_DATA, ICW4_8086);
+        io_wait();
+        outb(SLAVE_DATA, ICW4_8086);
+        io_wait();
+
+        // drivers unmask their lines once they can handle them
+        outb(MASTER_DATA, !bit!(CASCADE_IRQ));
+        outb(SLAVE_DATA, 0xff);
+    }
+    DISABLED.store(false, Ordering::SeqCst);
+
+    let mut idt = idt::IDT.lock();
+    for vector in VECTOR_BASE..VECTOR_BASE + IRQ_COUNT {
+        unsafe { idt.set_raw_handler(vector, trap::stub_addr(vector)) };
+    }
+}
+
+/// Masks every line for good, used once the APIC delivers the interrupts.
+///
+/// The PICs stay remapped, so a spurious interrupt they might still raise doesn't end up on an
+/// exception vector.
+pub fn disable() {
+    unsafe {
+        outb(MASTER_DATA, 0xff);
+        outb(SLAVE_DATA, 0xff);
+    }
+    DISABLED.store(true, Ordering::SeqCst);
+}
+
+pub fn is_disabled() -> bool {
+    DISABLED.load(Ordering::SeqCst)
+}
+
+/// Stops `irq` from being delivered.
+pub fn mask(irq: u8) {
+    let (port, line) = data_port(irq);
+    without_interrupts(|| unsafe { outb(port, inb(port) | bit!(line)) });
+}
+
+/// Lets `irq` through. Unmasking a line of the slave unmasks the cascade as well.
+pub fn unmask(irq: u8) {
+    let (port, line) = data_port(irq);
+    without_interrupts(|| unsafe {
+        outb(port, inb(port) & !bit!(line));
+        if irq >= 8 {
+            outb(MASTER_DATA, inb(MASTER_DATA) & !bit!(CASCADE_IRQ));
+        }
+    });
+}
+
+pub fn is_masked(irq: u8) -> bool {
+    let (port, line) = data_port(irq);
+    unsafe { inb(port) & bit!(line) != 0 }
+}
+
+/// Signals the end of the handler for `irq`. IRQs of the slave have to be acknowledged at both
+/// PICs.
+pub fn end_of_interrupt(irq: u8) {
+    unsafe {
+        if irq >= 8 {
+            outb(SLAVE_COMMAND, OCW2_EOI);
+        }
+        outb(MASTER_COMMAND, OCW2_EOI);
+    }
+}
+
+/// Number of spurious interrupts seen on IRQ 7 and 15.
+pub fn spurious_count() -> u64 {
+    SPURIOUS.load(Ordering::Relaxed)
+}
+
+/// Handles an interrupt on one of the PIC vectors. A line without a driver is masked again, so it
+/// can't flood us.
+pub fn handle(vector: u8) {
+    let irq = vector - VECTOR_BASE;
+    if is_spurious(irq) {
+        SPURIOUS.fetch_add(1, Ordering::Relaxed);
+        trace!("spurious irq {irq}");
+        // the master did raise the cascade line for a spurious interrupt of the slave
+        if irq == 15 {
+            end_of_interrupt(CASCADE_IRQ);
+        }
+        return;
+    }
+    if !irq::dispatch(vector) {
+        warn!("unhandled irq {irq}, masking it");
+        mask(irq);
+    }
+    end_of_interrupt(irq);
+}
+
+/// The lowest priority line of each PIC gets raised when a request goes away before the PIC
+/// could deliver it. Such an interrupt is not marked in service and must not be acknowledged.
+fn is_spurious(irq: u8) -> bool {
+    let (port, line) = match irq {
+        7 => (MASTER_COMMAND, 7),
+        15 => (SLAVE_COMMAND, 7),
+        _ => return false,
+    };
+    unsafe {
+        outb(port, OCW3_READ_ISR);
+        inb(port) & bit!(line) == 0
+    }
+}
+
+/// Data port of the PIC responsible for `irq` and the line of `irq` at that PIC.
+fn data_port(irq: u8) -> (u16, u8) {
+    assert!(irq < IRQ_COUNT, "the PICs have no irq {irq}");
+    if irq < 8 {
+        (MASTER_DATA, irq)
+    } else {
+        (SLAVE_DATA, irq - 8)
+    }
+}
+
+fn io_wait() {
+    unsafe { outb(IO_WAIT_PORT, 0) };
+}
@@ -0,0 +1,41 @@
+use core::arch::asm;
+
+/// Reads a byte from the I/O port `port`.
+///
+/// # Safety
+///
+/// Reading a port can have side effects on the device behind it.
+pub unsafe fn inb(port: u16) -> u8 {
+    let value: u8;
+    asm!("in al, dx", out("al") value, in("dx") port, options(nomem, nostack, preserves_flags));
+    value
+}
+
+/// Writes a byte to the I/O port `port`.
+///
+/// # Safety
+///
+/// Writing a port can have arbitrary side effects on the device behind it.
+pub unsafe fn outb(port: u16, value: u8) {
+    asm!("out dx, al", in("dx") port, in("al") value, options(nomem, nostack, preserves_flags));
+}
+
+/// Reads a double word from the I/O port `port`.
+///
+/// # Safety
+///
+/// Reading a port can have side effects on the device behind it.
+pub unsafe fn inl(port: u16) -> u32 {
+    let value: u32;
+    asm!("in eax, dx", out("eax") value, in("dx") port, options(nomem, nostack, preserves_flags));
+    value
+}
+
+/// Writes a double word to the I/O port `port`.
+///
+/// # Safety
+///
+/// Writing a port can have arbitrary side effects on the device behind it.
+pub unsafe fn outl(port: u16, value: u32) {
+    asm!("out dx, eax", in("dx") port, in("eax") value, options(nomem, nostack, preserves_flags));
+}
@@ -0,0 +1,55 @@
+// Devices only QEMU (and partly Bochs) provide, for debugging and scripted runs.
+// `make run` enables both of them:
+// -debugcon file:out/debugcon.log -device isa-debug-exit,iobase=0xf4,iosize=0x04
+
+use core::fmt;
+
+use spin::Mutex;
+
+use crate::arch::x86_64::halt;
+use crate::arch::x86_64::port::{inb, outb, outl};
+
+/// Port of the debug console (`-debugcon`).
+pub const DEBUGCON_PORT: u16 = 0xE9;
+
+/// Port of the `isa-debug-exit` device, as configured in the GNUmakefile.
+pub const DEBUG_EXIT_PORT: u16 = 0xF4;
+
+pub static DEBUGCON: Mutex<DebugCon> = Mutex::new(DebugCon);
+
+/// The debug console, every byte written to its port shows up on the host. It needs no setup at
+/// all, which makes it the sink of choice for the earliest boot code.
+pub struct DebugCon;
+
+impl fmt::Write for DebugCon {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            unsafe { outb(DEBUGCON_PORT, byte) };
+        }
+        Ok(())
+    }
+}
+
+/// Whether we run inside QEMU (or Bochs) with the debug console enabled.
+///
+/// The port reads back 0xE9 in that case. On real hardware it's usually unused and reads 0xFF.
+pub fn is_present() -> bool {
+    unsafe { inb(DEBUGCON_PORT) == DEBUGCON_PORT as u8 }
+}
+
+/// Exit status handed to `isa-debug-exit`. QEMU exits with `(code << 1) | 1`, so no code can
+/// produce 0 and the values are picked to not collide with QEMU's own errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ExitCode {
+    /// QEMU exits with 33.
+    Success = 0x10,
+    /// QEMU exits with 35.
+    Failed = 0x11,
+}
+
+/// Ends the VM with `code`. Halts if there is no `isa-debug-exit` device, e.g. on real hardware.
+pub fn exit(code: ExitCode) -> ! {
+    unsafe { outl(DEBUG_EXIT_PORT, code as u32) };
+    halt();
+}
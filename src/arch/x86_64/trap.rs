@@ -0,0 +1,200 @@
+use core::arch::global_asm;
+use core::fmt;
+use core::ptr::addr_of;
+
+use crate::arch::x86_64::idt::InterruptStackFrame;
+use crate::arch::x86_64::{apic, exceptions, irq, pic};
+
+/// Every vector has an entry stub, the local APIC uses the ones at the top.
+pub const STUB_COUNT: usize = 256;
+
+/// The vectors below are reserved for exceptions.
+const FIRST_INTERRUPT_VECTOR: u8 = 32;
+
+/// Every stub is padded to the same size, so the stub of a vector can be found without a table.
+const STUB_SIZE: u64 = 16;
+
+/// The complete state of the interrupted context, built by the entry stubs on the stack of the
+/// handler.
+///
+/// The general purpose registers and the [`InterruptStackFrame`] are restored from the frame on
+/// return, so changes to them take effect in the interrupted context. The control registers are
+/// only a snapshot and are not written back.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct TrapFrame {
+    pub cr0: u64,
+    pub cr2: u64,
+    pub cr3: u64,
+    pub cr4: u64,
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub vector: u64,
+    /// The error code pushed by the processor, 0 for vectors without one.
+    pub error_code: u64,
+    pub stack_frame: InterruptStackFrame,
+}
+
+impl fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = &self.stack_frame;
+        writeln!(
+            f,
+            "RIP={:016x} CS={:04x} RFLAGS={:016x}",
+            s.instruction_pointer, s.code_segment, s.cpu_flags
+        )?;
+        writeln!(f, "RSP={:016x} SS={:04x}", s.stack_pointer, s.stack_segment)?;
+        writeln!(
+            f,
+            "RAX={:016x} RBX={:016x} RCX={:016x}",
+            self.rax, self.rbx, self.rcx
+        )?;
+        writeln!(
+            f,
+            "RDX={:016x} RSI={:016x} RDI={:016x}",
+            self.rdx, self.rsi, self.rdi
+        )?;
+        writeln!(
+            f,
+            "RBP={:016x} R8 ={:016x} R9 ={:016x}",
+            self.rbp, self.r8, self.r9
+        )?;
+        writeln!(
+            f,
+            "R10={:016x} R11={:016x} R12={:016x}",
+            self.r10, self.r11, self.r12
+        )?;
+        writeln!(
+            f,
+            "R13={:016x} R14={:016x} R15={:016x}",
+            self.r13, self.r14, self.r15
+        )?;
+        write!(
+            f,
+            "CR0={:016x} CR2={:016x} CR3={:016x} CR4={:016x}",
+            self.cr0, self.cr2, self.cr3, self.cr4
+        )
+    }
+}
+
+/// Address of the entry stub for `vector`, to be installed in the IDT.
+pub fn stub_addr(vector: u8) -> u64 {
+    assert!(
+        (vector as usize) < STUB_COUNT,
+        "no entry stub for vector {vector}"
+    );
+    unsafe { addr_of!(trap_stubs) as u64 + vector as u64 * STUB_SIZE }
+}
+
+extern "C" {
+    static trap_stubs: u8;
+}
+
+// Called by `trap_common` with the frame it just built.
+#[no_mangle]
+extern "C" fn trap_dispatch(frame: &mut TrapFrame) {
+    irq::count(frame.vector as u8);
+    match frame.vector as u8 {
+        vector if vector < FIRST_INTERRUPT_VECTOR => exceptions::handle(frame),
+        vector if (pic::VECTOR_BASE..pic::VECTOR_BASE + pic::IRQ_COUNT).contains(&vector) => {
+            pic::handle(vector)
+        }
+        vector @ (apic::TIMER_VECTOR | apic::ERROR_VECTOR | apic::SPURIOUS_VECTOR) => {
+            apic::handle(vector)
+        }
+        vector => irq::handle(vector),
+    }
+}
+
+// Each stub pushes a dummy error code if the processor doesn't push one, so all frames look the
+// same, then the vector and continues in `trap_common`.
+//
+// On entry the processor aligned rsp to 16 bytes before pushing the 5 qwords of the interrupt
+// stack frame. The error code, vector, 15 general purpose and 4 control registers add up to an
+// even number of qwords again, so `trap_dispatch` is called with a properly aligned stack.
+global_asm!(
+    r#"
+    .section .text
+    .global trap_stubs
+    .p2align 4
+trap_stubs:
+    trap_vector = 0
+    .rept {stub_count}
+0:
+    .if (trap_vector == 8) | (trap_vector == 10) | (trap_vector == 11) | (trap_vector == 12) | (trap_vector == 13) | (trap_vector == 14) | (trap_vector == 17) | (trap_vector == 21) | (trap_vector == 29) | (trap_vector == 30)
+    .else
+    pushq $0
+    .endif
+    pushq $trap_vector
+    jmp trap_common
+    .fill 0b + {stub_size} - ., 1, 0xcc
+    trap_vector = trap_vector + 1
+    .endr
+
+trap_common:
+    pushq %r15
+    pushq %r14
+    pushq %r13
+    pushq %r12
+    pushq %r11
+    pushq %r10
+    pushq %r9
+    pushq %r8
+    pushq %rbp
+    pushq %rdi
+    pushq %rsi
+    pushq %rdx
+    pushq %rcx
+    pushq %rbx
+    pushq %rax
+    movq %cr4, %rax
+    pushq %rax
+    movq %cr3, %rax
+    pushq %rax
+    movq %cr2, %rax
+    pushq %rax
+    movq %cr0, %rax
+    pushq %rax
+
+    movq %rsp, %rdi
+    cld
+    call trap_dispatch
+
+    // the control registers are not restored
+    addq $32, %rsp
+    popq %rax
+    popq %rbx
+    popq %rcx
+    popq %rdx
+    popq %rsi
+    popq %rdi
+    popq %rbp
+    popq %r8
+    popq %r9
+    popq %r10
+    popq %r11
+    popq %r12
+    popq %r13
+    popq %r14
+    popq %r15
+    // vector and error code
+    addq $16, %rsp
+    iretq
+    "#,
+    stub_count = const STUB_COUNT,
+    stub_size = const STUB_SIZE,
+    options(att_syntax)
+);
@@ -0,0 +1,59 @@
+// Bytes typed on the keyboard or received on the serial port. The interrupt handlers of the
+// devices queue them until the shell reads them.
+
+use spin::Mutex;
+
+use crate::arch::x86_64::{disable_interrupts, enable_interrupts, enable_interrupts_and_halt};
+
+const CAPACITY: usize = 256;
+
+static QUEUE: Mutex<InputQueue> = Mutex::new(InputQueue {
+    bytes: [0; CAPACITY],
+    head: 0,
+    len: 0,
+});
+
+struct InputQueue {
+    bytes: [u8; CAPACITY],
+    /// Index of the oldest byte.
+    head: usize,
+    len: usize,
+}
+
+/// Queues `byte`, called by the interrupt handlers. The byte is dropped if nobody read the last
+/// [`CAPACITY`] bytes.
+pub fn push(byte: u8) {
+    // the handlers run with interrupts disabled, readers disable them while holding the lock
+    let mut queue = QUEUE.lock();
+    if queue.len == CAPACITY {
+        return;
+    }
+    let tail = (queue.head + queue.len) % CAPACITY;
+    queue.bytes[tail] = byte;
+    queue.len += 1;
+}
+
+/// Waits for the next byte. The processor sleeps until an interrupt queues one.
+///
+/// Interrupts are enabled when this returns, without them nothing could ever arrive anyway.
+pub fn read() -> u8 {
+    loop {
+        disable_interrupts();
+        if let Some(byte) = pop() {
+            enable_interrupts();
+            return byte;
+        }
+        enable_interrupts_and_halt();
+    }
+}
+
+fn pop() -> Option<u8> {
+    let mut queue = QUEUE.lock();
+    if queue.len == 0 {
+        return None;
+    }
+    let byte = queue.bytes[queue.head];
+    queue.head = (queue.head + 1) % CAPACITY;
+    queue.len -= 1;
+    Some(byte)
+}
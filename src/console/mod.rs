@@ -0,0 +1,201 @@
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+
+use spin::{Mutex, RwLock};
+
+use crate::klog;
+
+pub mod input;
+pub mod shell;
+
+const MAX_SINKS: usize = 8;
+
+/// Consecutive failed writes after which a sink is disabled.
+const MAX_FAILURES: u32 = 8;
+
+static SINKS: RwLock<[Option<SinkEntry>; MAX_SINKS]> = RwLock::new([const { None }; MAX_SINKS]);
+
+#[macro_export]
+macro_rules! println {
+    () => {
+        $crate::print!("\n")
+    };
+    ($($arg:tt)*) => {{
+        $crate::console::_print(core::format_args!("{}\n", core::format_args!($($arg)*)));
+    }};
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => {{
+        $crate::console::_print(core::format_args!($($arg)*));
+    }};
+}
+
+pub fn _print(args: fmt::Arguments<'_>) {
+    write(Level::Info, args);
+}
+
+/// Severity of a message. Sinks only get messages at least as severe as their level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+    Trace = 4,
+}
+
+impl Level {
+    pub(crate) fn from_u8(level: u8) -> Level {
+        match level {
+            0 => Level::Error,
+            1 => Level::Warn,
+            2 => Level::Info,
+            3 => Level::Debug,
+            _ => Level::Trace,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "error" => Some(Level::Error),
+            "warn" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => None,
+        }
+    }
+}
+
+/// An output device of the console.
+pub trait Sink: Sync {
+    fn write_fmt(&self, args: fmt::Arguments<'_>) -> Result<(), SinkError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkError {
+    /// The sink is in use, e.g. by the code an exception interrupted. The message is dropped for
+    /// this sink.
+    Busy,
+    /// The device reported an error.
+    Failed,
+}
+
+/// Any writer behind a lock can be a sink. The lock is only tried, waiting for it would
+/// deadlock if the holder got interrupted by a handler that prints.
+impl<T: fmt::Write + Send> Sink for Mutex<T> {
+    fn write_fmt(&self, args: fmt::Arguments<'_>) -> Result<(), SinkError> {
+        let mut writer = self.try_lock().ok_or(SinkError::Busy)?;
+        fmt::Write::write_fmt(&mut *writer, args).map_err(|_| SinkError::Failed)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleError {
+    /// All sink slots are taken.
+    TooManySinks,
+    /// A sink with this name is already registered.
+    AlreadyRegistered,
+    NoSuchSink,
+}
+
+struct SinkEntry {
+    name: &'static str,
+    sink: &'static dyn Sink,
+    level: AtomicU8,
+    failures: AtomicU32,
+    enabled: AtomicBool,
+}
+
+impl SinkEntry {
+    fn write(&self, level: Level, args: fmt::Arguments<'_>) {
+        if !self.enabled.load(Ordering::Relaxed)
+            || level > Level::from_u8(self.level.load(Ordering::Relaxed))
+        {
+            return;
+        }
+        match self.sink.write_fmt(args) {
+            Ok(()) => self.failures.store(0, Ordering::Relaxed),
+            Err(SinkError::Busy) => {}
+            Err(SinkError::Failed) => {
+                let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+                if failures >= MAX_FAILURES {
+                    self.enabled.store(false, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
+
+/// Adds a sink that receives all messages with at least `level` from now on.
+pub fn register(
+    name: &'static str,
+    sink: &'static dyn Sink,
+    level: Level,
+) -> Result<(), ConsoleError> {
+    let mut sinks = SINKS.write();
+    if sinks.iter().flatten().any(|entry| entry.name == name) {
+        return Err(ConsoleError::AlreadyRegistered);
+    }
+    let slot = sinks
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or(ConsoleError::TooManySinks)?;
+    *slot = Some(SinkEntry {
+        name,
+        sink,
+        level: AtomicU8::new(level as u8),
+        failures: AtomicU32::new(0),
+        enabled: AtomicBool::new(true),
+    });
+    drop(sinks);
+    // the first sink gets to see everything that was logged before it existed
+    klog::flush();
+    Ok(())
+}
+
+pub fn unregister(name: &str) -> Result<(), ConsoleError> {
+    let mut sinks = SINKS.write();
+    let slot = sinks
+        .iter_mut()
+        .find(|slot| slot.as_ref().is_some_and(|entry| entry.name == name))
+        .ok_or(ConsoleError::NoSuchSink)?;
+    *slot = None;
+    Ok(())
+}
+
+/// Changes the level of a sink. This also enables a sink that was disabled after failing.
+pub fn set_level(name: &str, level: Level) -> Result<(), ConsoleError> {
+    let sinks = SINKS.read();
+    let entry = sinks
+        .iter()
+        .flatten()
+        .find(|entry| entry.name == name)
+        .ok_or(ConsoleError::NoSuchSink)?;
+    entry.level.store(level as u8, Ordering::Relaxed);
+    entry.failures.store(0, Ordering::Relaxed);
+    entry.enabled.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+pub fn has_sinks() -> bool {
+    SINKS
+        .try_read()
+        .is_some_and(|sinks| sinks.iter().any(|slot| slot.is_some()))
+}
+
+/// Writes a message to every sink whose level allows it.
+///
+/// Messages written before any sink is registered are lost.
+pub fn write(level: Level, args: fmt::Arguments<'_>) {
+    // a sink being registered right now is not worth waiting for
+    let Some(sinks) = SINKS.try_read() else {
+        return;
+    };
+    for entry in sinks.iter().flatten() {
+        entry.write(level, args);
+    }
+}
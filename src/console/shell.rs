@@ -0,0 +1,101 @@
+use core::time::Duration;
+
+use crate::arch::x86_64::qemu::ExitCode;
+use crate::arch::x86_64::{irq, qemu};
+use crate::console::{input, Level};
+use crate::mem::vmalloc;
+use crate::time::Instant;
+use crate::{klog, print, println, time};
+
+const MAX_LINE: usize = 128;
+
+// ASCII control characters sent by terminals
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
+
+/// Reads commands from the keyboard and the serial port and executes them, forever.
+pub fn run() -> ! {
+    let mut line = [0u8; MAX_LINE];
+    let mut len = 0;
+    print!("> ");
+    loop {
+        let byte = input::read();
+        match byte {
+            b'\r' | b'\n' => {
+                println!();
+                // only printable ASCII ever ends up in the buffer
+                let command = core::str::from_utf8(&line[..len]).unwrap_or("");
+                execute(command);
+                len = 0;
+                print!("> ");
+            }
+            BACKSPACE | DELETE if len > 0 => {
+                len -= 1;
+                print!("\x08 \x08");
+            }
+            0x20..=0x7e if len < MAX_LINE => {
+                line[len] = byte;
+                len += 1;
+                print!("{}", byte as char);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn execute(command: &str) {
+    let mut args = command.split_whitespace();
+    match args.next() {
+        None => {}
+        Some("help") => {
+            println!("help                     show this text");
+            println!("dmesg                    print the kernel log");
+            println!("loglevel [module] level  filter the log of a module, or of all modules");
+            println!("vmregions                list the vmalloc regions");
+            println!("interrupts               count the interrupts of every vector");
+            println!("uptime                   time since boot and the clocksource");
+            println!("date                     the date and time in UTC");
+            println!("exit                     end QEMU successfully");
+        }
+        Some("dmesg") => klog::dump(),
+        Some("loglevel") => loglevel(args.next(), args.next()),
+        Some("vmregions") => vmalloc::print_regions(),
+        Some("interrupts") => irq::print_interrupts(),
+        Some("uptime") => uptime(),
+        Some("date") => println!("{} UTC", time::date_now()),
+        Some("exit") => qemu::exit(ExitCode::Success),
+        Some(unknown) => println!("unknown command: {unknown}, try help"),
+    }
+}
+
+fn loglevel(first: Option<&str>, second: Option<&str>) {
+    let (module, level) = match (first, second) {
+        (Some(level), None) => (None, level),
+        (Some(module), Some(level)) => (Some(module), level),
+        _ => {
+            println!("usage: loglevel [module] error|warn|info|debug|trace");
+            return;
+        }
+    };
+    let Some(level) = Level::from_name(level) else {
+        println!("unknown level: {level}");
+        return;
+    };
+    match module {
+        None => klog::set_default_level(level),
+        Some(module) => {
+            if let Err(e) = klog::set_module_level(module, level) {
+                println!("couldn't set the level: {e:?}");
+            }
+        }
+    }
+}
+
+fn uptime() {
+    let uptime = Duration::from_nanos(Instant::now().as_nanos());
+    let (secs, millis) = (uptime.as_secs(), uptime.subsec_millis());
+    match time::clocksource_name() {
+        Some(source) => println!("up {secs}.{millis:03}s, clocksource {source}"),
+        None => println!("the clock isn't running yet"),
+    }
+}
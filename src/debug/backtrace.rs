@@ -0,0 +1,88 @@
+use core::arch::asm;
+use core::fmt;
+
+use yashima_core::paging::VirtAddr;
+
+use crate::arch::x86_64::mapper;
+use crate::debug::symbols;
+use crate::{error, println};
+
+/// Upper bound for the frames printed, in case the chain loops or runs into garbage.
+const MAX_FRAMES: usize = 64;
+
+/// Where the lines of a backtrace go.
+type Output = fn(fmt::Arguments<'_>);
+
+/// Prints the call stack of the caller.
+///
+/// Goes straight to the console, the panic may have happened while the log was locked.
+#[inline(never)]
+pub fn print() {
+    let rbp: u64;
+    unsafe {
+        asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags));
+    }
+    println!("backtrace:");
+    unsafe { walk(rbp, 0, to_console) };
+}
+
+/// Logs the call stack of an interrupted context as errors, starting with the instruction at
+/// `rip`.
+pub fn log_from(rip: u64, rbp: u64) {
+    error!("backtrace:");
+    print_frame(0, rip, to_log);
+    unsafe { walk(rbp, 1, to_log) };
+}
+
+fn to_console(args: fmt::Arguments<'_>) {
+    println!("{args}");
+}
+
+fn to_log(args: fmt::Arguments<'_>) {
+    error!("{args}");
+}
+
+/// Follows the chain of saved frame pointers. The kernel is built with frame pointers, so every
+/// frame starts with the caller's rbp followed by the return address.
+unsafe fn walk(mut rbp: u64, first: usize, out: Output) {
+    for depth in first..MAX_FRAMES {
+        if !is_valid_frame(rbp) {
+            return;
+        }
+        let frame = rbp as *const u64;
+        let return_addr = *frame.add(1);
+        if return_addr == 0 {
+            return;
+        }
+        // the return address points behind the call, which may already be the next function
+        print_frame(depth, return_addr - 1, out);
+
+        let next = *frame;
+        // the stack grows down, so callers always have higher frame pointers
+        if next <= rbp {
+            return;
+        }
+        rbp = next;
+    }
+    out(format_args!("  ..."));
+}
+
+fn is_valid_frame(rbp: u64) -> bool {
+    if rbp == 0 || rbp % 8 != 0 {
+        return false;
+    }
+    let (Ok(start), Ok(end)) = (VirtAddr::try_new(rbp), VirtAddr::try_new(rbp + 15)) else {
+        return false;
+    };
+    mapper::is_mapped(start) && mapper::is_mapped(end)
+}
+
+fn print_frame(depth: usize, addr: u64, out: Output) {
+    match symbols::resolve(addr) {
+        Some(symbol) => out(format_args!(
+            "  #{depth:<2} {addr:#x} {}+{:#x}",
+            symbol.name, symbol.offset
+        )),
+        None => out(format_args!("  #{depth:<2} {addr:#x} <unknown>")),
+    }
+}
@@ -0,0 +1,2 @@
+pub mod backtrace;
+pub mod symbols;
@@ -0,0 +1,83 @@
+use limine::request::{KernelAddressRequest, ModuleRequest};
+use spin::Once;
+
+#[used]
+static MODULE_REQUEST: ModuleRequest = ModuleRequest::new();
+#[used]
+static KERNEL_ADDRESS_REQUEST: KernelAddressRequest = KernelAddressRequest::new();
+
+/// The address the kernel is linked at, see `linker.ld`.
+const LINK_BASE: u64 = 0xffffffff80000000;
+
+/// Name of the module the GNUmakefile generates with `nm`. Every line holds one function:
+/// `<address in hex> <type> <demangled name>`, sorted by address.
+const SYMBOL_MODULE: &[u8] = b"yashima.sym";
+
+static SYMBOLS: Once<SymbolTable> = Once::new();
+
+struct SymbolTable {
+    data: &'static str,
+    /// How far limine moved the kernel away from [`LINK_BASE`] (KASLR).
+    slide: u64,
+}
+
+/// A resolved code address.
+#[derive(Debug, Clone, Copy)]
+pub struct Symbol {
+    pub name: &'static str,
+    /// Distance of the address from the start of the function.
+    pub offset: u64,
+}
+
+/// Picks up the symbol module loaded by limine. Without it addresses are printed unresolved.
+pub fn init() {
+    let slide = kaslr_slide();
+    let Some(modules) = MODULE_REQUEST.get_response() else {
+        return;
+    };
+    let Some(module) = modules
+        .modules()
+        .iter()
+        .find(|module| module.path().ends_with(SYMBOL_MODULE))
+    else {
+        return;
+    };
+    let bytes = unsafe { core::slice::from_raw_parts(module.addr(), module.size() as usize) };
+    if let Ok(data) = core::str::from_utf8(bytes) {
+        SYMBOLS.call_once(|| SymbolTable { data, slide });
+    }
+}
+
+/// Difference between the address the kernel runs at and the one it is linked at.
+pub fn kaslr_slide() -> u64 {
+    KERNEL_ADDRESS_REQUEST.get_response().map_or(0, |response| {
+        response.virtual_base().wrapping_sub(LINK_BASE)
+    })
+}
+
+/// Finds the function containing the runtime address `addr`.
+pub fn resolve(addr: u64) -> Option<Symbol> {
+    let table = SYMBOLS.get()?;
+    let link_addr = addr.wrapping_sub(table.slide);
+
+    let mut best: Option<(u64, &'static str)> = None;
+    for line in table.data.lines() {
+        let mut fields = line.splitn(3, ' ');
+        let (Some(start), Some(_), Some(name)) = (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let Ok(start) = u64::from_str_radix(start, 16) else {
+            continue;
+        };
+        // the table is sorted, the last symbol below the address is the containing function
+        if start > link_addr {
+            break;
+        }
+        best = Some((start, name));
+    }
+    best.map(|(start, name)| Symbol {
+        name,
+        offset: link_addr - start,
+    })
+}
@@ -0,0 +1,99 @@
+// The high precision event timer. We only use its main counter, as a clocksource and as the
+// reference the TSC is calibrated against. For the registers refer to the IA-PC HPET
+// specification, revision 1.0a.
+
+use spin::Once;
+use yashima_core::bit;
+
+use crate::mem::mmio::{ioremap, CacheType, MmioRegion};
+use crate::time::Clocksource;
+use crate::{acpi, info};
+
+const CAPABILITIES: usize = 0x00;
+const CONFIGURATION: usize = 0x10;
+const MAIN_COUNTER: usize = 0xf0;
+const MMIO_SIZE: usize = 0x400;
+
+// capabilities, the upper half holds the period of the main counter
+const COUNTER_64BIT: u64 = bit!(13);
+const PERIOD_SHIFT: u64 = 32;
+
+// configuration
+const ENABLE: u64 = bit!(0);
+
+/// The period is given in femtoseconds.
+const FEMTOS_PER_SEC: u64 = 1_000_000_000_000_000;
+/// The specification doesn't allow periods above 100ns.
+const MAX_PERIOD: u64 = 100_000_000;
+
+static HPET: Once<Hpet> = Once::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HpetError {
+    /// There is no HPET table.
+    NotPresent,
+    /// The period of the main counter in femtoseconds is 0 or out of spec.
+    BadPeriod(u64),
+}
+
+pub struct Hpet {
+    regs: MmioRegion,
+    frequency: u64,
+    counter_64bit: bool,
+}
+
+impl Clocksource for Hpet {
+    fn name(&self) -> &'static str {
+        "hpet"
+    }
+
+    fn frequency(&self) -> u64 {
+        self.frequency
+    }
+
+    fn mask(&self) -> u64 {
+        if self.counter_64bit {
+            u64::MAX
+        } else {
+            u32::MAX as u64
+        }
+    }
+
+    fn read(&self) -> u64 {
+        if self.counter_64bit {
+            self.regs.read64(MAIN_COUNTER)
+        } else {
+            self.regs.read32(MAIN_COUNTER) as u64
+        }
+    }
+}
+
+/// Maps the HPET of the ACPI table and starts its main counter.
+///
+/// The timers stay as the firmware left them, legacy replacement stays off so the PIT and the
+/// RTC keep their interrupts.
+pub fn init() -> Result<&'static Hpet, HpetError> {
+    let table = acpi::tables()
+        .and_then(|tables| tables.hpet.as_ref())
+        .ok_or(HpetError::NotPresent)?;
+    let regs = unsafe { ioremap(table.base_address, MMIO_SIZE, CacheType::Uncacheable) };
+    let capabilities = regs.read64(CAPABILITIES);
+    let period = capabilities >> PERIOD_SHIFT;
+    if period == 0 || period > MAX_PERIOD {
+        return Err(HpetError::BadPeriod(period));
+    }
+    regs.write64(CONFIGURATION, regs.read64(CONFIGURATION) | ENABLE);
+
+    let hpet = Hpet {
+        regs,
+        frequency: FEMTOS_PER_SEC / period,
+        counter_64bit: capabilities & COUNTER_64BIT != 0,
+    };
+    info!(
+        "hpet at {:#x}, {} kHz, {} bit counter",
+        table.base_address,
+        hpet.frequency / 1000,
+        if hpet.counter_64bit { 64 } else { 32 }
+    );
+    Ok(HPET.call_once(|| hpet))
+}
@@ -0,0 +1,87 @@
+// The PS/2 keyboard behind the 8042 controller. The firmware leaves the controller translating
+// to scancode set 1, which we turn into ASCII for the console input with a US layout.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use yashima_core::bit;
+
+use crate::arch::x86_64::irq::IrqReturn;
+use crate::arch::x86_64::port::inb;
+use crate::console::input;
+
+/// ISA IRQ of the keyboard.
+pub const IRQ: u8 = 1;
+
+const DATA: u16 = 0x60;
+const STATUS: u16 = 0x64;
+
+// status
+const OUTPUT_FULL: u8 = bit!(0);
+
+/// Set in the scancode when the key is released.
+const RELEASED: u8 = bit!(7);
+/// Prefix of the scancodes of the extended keys (arrows, keypad enter, ...).
+const EXTENDED: u8 = 0xe0;
+const LEFT_SHIFT: u8 = 0x2a;
+const RIGHT_SHIFT: u8 = 0x36;
+
+static SHIFT: AtomicBool = AtomicBool::new(false);
+static EXTENDED_PENDING: AtomicBool = AtomicBool::new(false);
+
+// set 1 make codes up to the space bar, 0 for keys without a character
+const NORMAL: &[u8; 0x3a] =
+    b"\0\x1b1234567890-=\x08\tqwertyuiop[]\n\0asdfghjkl;'`\0\\zxcvbnm,./\0*\0 ";
+const SHIFTED: &[u8; 0x3a] =
+    b"\0\x1b!@#$%^&*()_+\x08\tQWERTYUIOP{}\n\0ASDFGHJKL:\"~\0|ZXCVBNM<>?\0*\0 ";
+
+/// Drops whatever the controller still buffers from before, so the first interrupt isn't
+/// stuck behind an unread byte.
+pub fn init() {
+    unsafe {
+        while inb(STATUS) & OUTPUT_FULL != 0 {
+            inb(DATA);
+        }
+    }
+}
+
+/// Reads the scancode the keyboard sent and queues the character of the key.
+pub fn handle_interrupt(_context: usize) -> IrqReturn {
+    let scancode = unsafe {
+        if inb(STATUS) & OUTPUT_FULL == 0 {
+            return IrqReturn::NotMine;
+        }
+        inb(DATA)
+    };
+    if let Some(byte) = translate(scancode) {
+        input::push(byte);
+    }
+    IrqReturn::Handled
+}
+
+fn translate(scancode: u8) -> Option<u8> {
+    if scancode == EXTENDED {
+        EXTENDED_PENDING.store(true, Ordering::Relaxed);
+        return None;
+    }
+    // none of the extended keys has a character we'd need
+    if EXTENDED_PENDING.swap(false, Ordering::Relaxed) {
+        return None;
+    }
+
+    let released = scancode & RELEASED != 0;
+    match scancode & !RELEASED {
+        LEFT_SHIFT | RIGHT_SHIFT => {
+            SHIFT.store(!released, Ordering::Relaxed);
+            None
+        }
+        _ if released => None,
+        code => {
+            let table = if SHIFT.load(Ordering::Relaxed) {
+                SHIFTED
+            } else {
+                NORMAL
+            };
+            table.get(code as usize).copied().filter(|byte| *byte != 0)
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use crate::arch::x86_64::irq;
+use crate::warn;
+
+pub mod hpet;
+pub mod keyboard;
+pub mod pit;
+pub mod rtc;
+pub mod serial;
+
+/// Registers the interrupt handlers of the keyboard and COM1.
+pub fn init_interrupts() {
+    keyboard::init();
+    let keyboard_irq = irq::isa_irq(keyboard::IRQ);
+    if let Err(e) = irq::request_irq(keyboard_irq, "keyboard", keyboard::handle_interrupt, 0) {
+        warn!("no keyboard interrupts: {e:?}");
+    }
+
+    // the lock has to be dropped before requesting, a warning there is printed on COM1
+    let com1_present = {
+        let mut com1 = serial::COM1.lock();
+        com1.enable_receive_interrupt();
+        com1.is_initialized()
+    };
+    if com1_present {
+        let com1_irq = irq::isa_irq(serial::COM1_IRQ);
+        if let Err(e) = irq::request_irq(com1_irq, "com1", serial::handle_interrupt, 0) {
+            warn!("no com1 interrupts: {e:?}");
+        }
+    }
+}
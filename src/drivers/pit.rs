@@ -0,0 +1,92 @@
+// The 8253/8254 programmable interval timer. Its input clock has a fixed, known frequency, which
+// makes it the reference the other timers are calibrated against. Channel 0 counts freely as the
+// clocksource of last resort, channel 2 waits.
+
+use yashima_core::bit;
+
+use crate::arch::x86_64::port::{inb, outb};
+use crate::arch::x86_64::without_interrupts;
+use crate::time::Clocksource;
+
+/// Frequency of the PIT input clock in Hz.
+pub const FREQUENCY: u64 = 1_193_182;
+
+const CHANNEL0_DATA: u16 = 0x40;
+const CHANNEL2_DATA: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+/// Gate of channel 2 and the PC speaker, its output is readable here as well.
+const PORT_B: u16 = 0x61;
+
+// port B
+const CHANNEL2_GATE: u8 = bit!(0);
+const SPEAKER_ENABLE: u8 = bit!(1);
+const CHANNEL2_OUT: u8 = bit!(5);
+
+/// Channel 2, low then high byte of the count, mode 0 (interrupt on terminal count), binary.
+const CHANNEL2_ONESHOT: u8 = 0b1011_0000;
+/// Channel 0, low then high byte of the count, mode 2 (rate generator), binary.
+const CHANNEL0_RATE_GENERATOR: u8 = 0b0011_0100;
+/// Channel 0, latch the current count.
+const CHANNEL0_LATCH: u8 = 0b0000_0000;
+
+/// Channel 0 as a clocksource, see [`start_counter`].
+pub static COUNTER: Counter = Counter;
+
+pub struct Counter;
+
+impl Clocksource for Counter {
+    fn name(&self) -> &'static str {
+        "pit"
+    }
+
+    fn frequency(&self) -> u64 {
+        FREQUENCY
+    }
+
+    fn mask(&self) -> u64 {
+        u16::MAX as u64
+    }
+
+    fn read(&self) -> u64 {
+        let count = without_interrupts(|| unsafe {
+            outb(COMMAND, CHANNEL0_LATCH);
+            let low = inb(CHANNEL0_DATA);
+            u16::from_le_bytes([low, inb(CHANNEL0_DATA)])
+        });
+        // the channel counts down, this turns it into the ticks that went by
+        count.wrapping_neg() as u64
+    }
+}
+
+/// Lets channel 0 count down through all 16 bits over and over, it wraps every 55ms.
+///
+/// The channel raises IRQ 0 on every wrap, which stays masked.
+pub fn start_counter() {
+    without_interrupts(|| unsafe {
+        outb(COMMAND, CHANNEL0_RATE_GENERATOR);
+        // a count of 0 stands for 65536
+        outb(CHANNEL0_DATA, 0);
+        outb(CHANNEL0_DATA, 0);
+    });
+}
+
+/// Longest wait [`wait_ms`] supports, the count register only has 16 bits.
+pub const MAX_WAIT_MS: u64 = 0xffff * 1000 / FREQUENCY;
+
+/// Busy waits `ms` milliseconds on channel 2.
+///
+/// Channel 2 is only wired to the PC speaker, so unlike channel 0 it doesn't need an interrupt
+/// and doesn't disturb anything. `ms` must not exceed [`MAX_WAIT_MS`].
+pub fn wait_ms(ms: u64) {
+    assert!(ms <= MAX_WAIT_MS, "the pit can't wait {ms}ms");
+    let count = (FREQUENCY * ms / 1000) as u16;
+    without_interrupts(|| unsafe {
+        // open the gate but keep the speaker quiet
+        outb(PORT_B, (inb(PORT_B) & !SPEAKER_ENABLE) | CHANNEL2_GATE);
+        outb(COMMAND, CHANNEL2_ONESHOT);
+        outb(CHANNEL2_DATA, count as u8);
+        outb(CHANNEL2_DATA, (count >> 8) as u8);
+        // in mode 0 the output goes high once the count reaches zero
+        while inb(PORT_B) & CHANNEL2_OUT == 0 {}
+    });
+}
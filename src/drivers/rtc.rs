@@ -0,0 +1,73 @@
+// The real-time clock in the CMOS of the PC. It keeps the date across power cycles, in whatever
+// format the firmware chose, BCD or binary with 12 or 24 hours. We take it to run on UTC.
+
+use core::hint::spin_loop;
+
+use yashima_core::bit;
+use yashima_core::time::{DateTime, RtcRegisters};
+
+use crate::acpi;
+use crate::arch::x86_64::port::{inb, outb};
+use crate::arch::x86_64::without_interrupts;
+
+// the CMOS registers are reached through an index port, bit 7 of the index would disable NMIs
+const INDEX: u16 = 0x70;
+const DATA: u16 = 0x71;
+
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const DAY_OF_MONTH: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+const STATUS_A: u8 = 0x0a;
+const STATUS_B: u8 = 0x0b;
+
+// status A
+const UPDATE_IN_PROGRESS: u8 = bit!(7);
+
+/// Reads the date and time, to the second.
+///
+/// The century comes from the register the FADT names. Without one the year is taken to be in
+/// the 2000s.
+pub fn read() -> DateTime {
+    let century = acpi::tables()
+        .and_then(|tables| tables.fadt.as_ref())
+        .and_then(|fadt| fadt.century_register);
+    // the index has to stay selected until the data is read
+    without_interrupts(|| {
+        // an update can start right after the check, so read until two readings agree
+        let mut last = read_registers(century);
+        loop {
+            let registers = read_registers(century);
+            if registers == last {
+                return registers.decode();
+            }
+            last = registers;
+        }
+    })
+}
+
+fn read_registers(century: Option<u8>) -> RtcRegisters {
+    // once a second the RTC spends up to 2ms updating, the registers are inconsistent meanwhile
+    while read_cmos(STATUS_A) & UPDATE_IN_PROGRESS != 0 {
+        spin_loop();
+    }
+    RtcRegisters {
+        second: read_cmos(SECONDS),
+        minute: read_cmos(MINUTES),
+        hour: read_cmos(HOURS),
+        day: read_cmos(DAY_OF_MONTH),
+        month: read_cmos(MONTH),
+        year: read_cmos(YEAR),
+        century: century.map(read_cmos),
+        status_b: read_cmos(STATUS_B),
+    }
+}
+
+fn read_cmos(index: u8) -> u8 {
+    unsafe {
+        outb(INDEX, index);
+        inb(DATA)
+    }
+}
@@ -0,0 +1,186 @@
+use core::fmt;
+
+use spin::Mutex;
+use yashima_core::bit;
+
+use crate::arch::x86_64::irq::IrqReturn;
+use crate::arch::x86_64::port::{inb, outb};
+use crate::console::input;
+
+/// I/O base of the first serial port.
+pub const COM1_BASE: u16 = 0x3F8;
+/// ISA IRQ of the first serial port.
+pub const COM1_IRQ: u8 = 4;
+
+/// The UART clock divided by 16, the baud rate for a divisor of 1.
+const MAX_BAUD: u32 = 115200;
+
+pub static COM1: Mutex<SerialPort> = Mutex::new(SerialPort::new(COM1_BASE));
+
+// register offsets from the base port
+// with DLAB set, DATA and INTERRUPT_ENABLE hold the low and high byte of the baud divisor
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+// interrupt enable
+const RECEIVED_DATA_AVAILABLE: u8 = bit!(0);
+
+// line control
+const DLAB: u8 = bit!(7);
+const EIGHT_DATA_BITS: u8 = 0b11;
+
+// FIFO control: enable, clear both FIFOs, interrupt when 14 bytes are waiting
+const FIFO_ENABLE_CLEAR_14: u8 = 0b1100_0111;
+
+// modem control
+const DTR: u8 = bit!(0);
+const RTS: u8 = bit!(1);
+const OUT2: u8 = bit!(3);
+const LOOPBACK: u8 = bit!(4);
+
+// line status
+const DATA_READY: u8 = bit!(0);
+const TRANSMIT_EMPTY: u8 = bit!(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialError {
+    /// The baud rate is 0, above 115200 or doesn't divide it.
+    InvalidBaudRate(u32),
+    /// The loopback test failed, there is likely no UART at the port.
+    NotPresent,
+}
+
+/// A 16550 compatible UART.
+///
+/// For further information refer to the [PC16550D datasheet](https://www.ti.com/lit/ds/symlink/pc16550d.pdf).
+pub struct SerialPort {
+    base: u16,
+    initialized: bool,
+}
+
+impl SerialPort {
+    pub const fn new(base: u16) -> SerialPort {
+        SerialPort {
+            base,
+            initialized: false,
+        }
+    }
+
+    /// Programs the port for `baud` baud with 8 data bits, no parity, 1 stop bit and enabled
+    /// FIFOs, then checks with a loopback test that the UART actually exists.
+    ///
+    /// Until this succeeded, everything written to the port is dropped.
+    pub fn init(&mut self, baud: u32) -> Result<(), SerialError> {
+        if baud == 0 || baud > MAX_BAUD || MAX_BAUD % baud != 0 {
+            return Err(SerialError::InvalidBaudRate(baud));
+        }
+        let divisor = (MAX_BAUD / baud) as u16;
+        unsafe {
+            // interrupts stay off until `enable_receive_interrupt`, before that input is polled
+            self.write_reg(INTERRUPT_ENABLE, 0);
+            self.write_reg(LINE_CONTROL, DLAB);
+            self.write_reg(DATA, divisor as u8);
+            self.write_reg(INTERRUPT_ENABLE, (divisor >> 8) as u8);
+            // 8N1, also clears DLAB again
+            self.write_reg(LINE_CONTROL, EIGHT_DATA_BITS);
+            self.write_reg(FIFO_CONTROL, FIFO_ENABLE_CLEAR_14);
+
+            // a byte sent in loopback mode has to come back unchanged
+            self.write_reg(MODEM_CONTROL, RTS | OUT2 | LOOPBACK);
+            self.write_reg(DATA, 0xAE);
+            if self.read_reg(DATA) != 0xAE {
+                return Err(SerialError::NotPresent);
+            }
+            self.write_reg(MODEM_CONTROL, DTR | RTS | OUT2);
+        }
+        self.initialized = true;
+        Ok(())
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.initialized
+    }
+
+    /// Raises an interrupt whenever a byte arrives, see [`handle_interrupt`].
+    pub fn enable_receive_interrupt(&mut self) {
+        if self.initialized {
+            unsafe { self.write_reg(INTERRUPT_ENABLE, RECEIVED_DATA_AVAILABLE) };
+        }
+    }
+
+    /// Sends `byte`, waiting until the transmitter has room for it.
+    pub fn send(&mut self, byte: u8) {
+        if !self.initialized {
+            return;
+        }
+        unsafe {
+            while self.read_reg(LINE_STATUS) & TRANSMIT_EMPTY == 0 {
+                core::hint::spin_loop();
+            }
+            self.write_reg(DATA, byte);
+        }
+    }
+
+    /// Returns the next received byte, if there is one.
+    pub fn try_receive(&mut self) -> Option<u8> {
+        if !self.initialized {
+            return None;
+        }
+        unsafe {
+            if self.read_reg(LINE_STATUS) & DATA_READY == 0 {
+                return None;
+            }
+            Some(self.read_reg(DATA))
+        }
+    }
+
+    /// Waits for the next received byte.
+    pub fn receive(&mut self) -> u8 {
+        loop {
+            if let Some(byte) = self.try_receive() {
+                return byte;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    unsafe fn read_reg(&self, reg: u16) -> u8 {
+        inb(self.base + reg)
+    }
+
+    unsafe fn write_reg(&self, reg: u16, value: u8) {
+        outb(self.base + reg, value)
+    }
+}
+
+/// Moves the bytes COM1 received to the console input.
+///
+/// This doesn't take the lock of [`COM1`], the interrupted code might hold it while sending.
+/// Reading the receiver doesn't disturb a send in progress.
+pub fn handle_interrupt(_context: usize) -> IrqReturn {
+    let mut result = IrqReturn::NotMine;
+    unsafe {
+        while inb(COM1_BASE + LINE_STATUS) & DATA_READY != 0 {
+            input::push(inb(COM1_BASE + DATA));
+            result = IrqReturn::Handled;
+        }
+    }
+    result
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            // terminals expect a carriage return before every line feed
+            if byte == b'\n' {
+                self.send(b'\r');
+            }
+            self.send(byte);
+        }
+        Ok(())
+    }
+}
@@ -1,9 +1,9 @@
 use core::fmt;
 
 use limine::framebuffer::Framebuffer;
+use yashima_core::font::PSFFont;
 
 use crate::font;
-use crate::font::PSFFont;
 
 #[derive(Debug, Clone, Copy)]
 #[repr(u32)]
@@ -74,7 +74,7 @@ impl<'a, 'b> CharBuffer<'a, 'b> {
     }
 
     fn add_character(&mut self, char: char) {
-        if self.caret as usize > self.charbuffer.len() {
+        if self.caret as usize >= self.charbuffer.len() {
             return;
         }
         self.charbuffer[self.caret as usize] = char;
@@ -101,7 +101,7 @@ impl<'a, 'b> CharBuffer<'a, 'b> {
     }
 
     fn new_line(&mut self) {
-        self.caret = (self.caret / self.chars_per_row) + self.chars_per_row;
+        self.caret = (self.caret / self.chars_per_row + 1) * self.chars_per_row;
     }
 
     fn clear_screen(&mut self) {
@@ -0,0 +1,379 @@
+use core::fmt;
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+use spin::Mutex;
+use yashima_core::time::{DateTime, NANOS_PER_SEC};
+
+pub use crate::console::Level;
+use crate::{console, time};
+
+/// Number of messages the ring keeps, older ones are overwritten.
+const RING_SIZE: usize = 256;
+
+/// Longer messages are cut off.
+const MAX_MESSAGE_LEN: usize = 200;
+
+const MAX_FILTERS: usize = 16;
+const MAX_FILTER_LEN: usize = 64;
+const OVERFLOW_SLOTS: usize = 8;
+
+static RING: Mutex<Ring> = Mutex::new(Ring::new());
+static FILTERS: Mutex<[Option<ModuleFilter>; MAX_FILTERS]> = Mutex::new([None; MAX_FILTERS]);
+static DEFAULT_LEVEL: AtomicU8 = AtomicU8::new(Level::Debug as u8);
+/// Messages of interrupt handlers that found the ring locked by the code they interrupted. The
+/// next [`log`] moves them into the ring.
+static OVERFLOW: [Mutex<Option<Record>>; OVERFLOW_SLOTS] =
+    [const { Mutex::new(None) }; OVERFLOW_SLOTS];
+/// Messages lost because the ring was locked and every overflow slot was taken.
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {{
+        $crate::klog::log($level, core::module_path!(), core::format_args!($($arg)*));
+    }};
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::log!($crate::klog::Level::Error, $($arg)*)
+    };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        $crate::log!($crate::klog::Level::Warn, $($arg)*)
+    };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::log!($crate::klog::Level::Info, $($arg)*)
+    };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        $crate::log!($crate::klog::Level::Debug, $($arg)*)
+    };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        $crate::log!($crate::klog::Level::Trace, $($arg)*)
+    };
+}
+
+#[derive(Clone, Copy)]
+struct Record {
+    /// Nanoseconds since boot when the message was logged, 0 before the clock runs.
+    timestamp: u64,
+    /// Seconds since the UNIX epoch when the message was logged, `None` before the wall clock
+    /// is synced.
+    date: Option<u64>,
+    level: Level,
+    module: &'static str,
+    text: [u8; MAX_MESSAGE_LEN],
+    len: usize,
+}
+
+impl Record {
+    const EMPTY: Record = Record {
+        timestamp: 0,
+        date: None,
+        level: Level::Trace,
+        module: "",
+        text: [0; MAX_MESSAGE_LEN],
+        len: 0,
+    };
+
+    fn text(&self) -> &str {
+        // `TextWriter` only ever cuts at char boundaries
+        core::str::from_utf8(&self.text[..self.len]).unwrap_or("<invalid utf-8>")
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self.level {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        };
+        write!(
+            f,
+            "[{:>5}.{:06}] ",
+            self.timestamp / NANOS_PER_SEC,
+            self.timestamp % NANOS_PER_SEC / 1000
+        )?;
+        if let Some(date) = self.date {
+            write!(f, "{} ", DateTime::from_unix(date))?;
+        }
+        write!(f, "{level:<5} {}: {}", self.module, self.text())
+    }
+}
+
+/// Fills the text of a record, dropping whatever doesn't fit.
+struct TextWriter<'a> {
+    record: &'a mut Record,
+}
+
+impl fmt::Write for TextWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let free = MAX_MESSAGE_LEN - self.record.len;
+        let mut end = s.len().min(free);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.record.text[self.record.len..self.record.len + end]
+            .copy_from_slice(&s.as_bytes()[..end]);
+        self.record.len += end;
+        Ok(())
+    }
+}
+
+struct Ring {
+    records: [Record; RING_SIZE],
+    /// Sequence number of the next message. The message with sequence number `n` lives at
+    /// `n % RING_SIZE`.
+    next: u64,
+    /// Sequence number of the oldest message that hasn't reached the console yet.
+    unprinted: u64,
+}
+
+impl Ring {
+    const fn new() -> Ring {
+        Ring {
+            records: [Record::EMPTY; RING_SIZE],
+            next: 0,
+            unprinted: 0,
+        }
+    }
+
+    fn oldest(&self) -> u64 {
+        self.next.saturating_sub(RING_SIZE as u64)
+    }
+
+    fn get(&self, seq: u64) -> &Record {
+        &self.records[(seq % RING_SIZE as u64) as usize]
+    }
+
+    fn push(&mut self, record: Record) {
+        self.records[(self.next % RING_SIZE as u64) as usize] = record;
+        self.next += 1;
+    }
+
+    /// Moves the messages that were parked in the overflow slots into the ring.
+    fn take_overflow(&mut self) {
+        for slot in &OVERFLOW {
+            // a slot that is locked is being filled right now, it's taken the next time
+            if let Some(record) = slot.try_lock().and_then(|mut slot| slot.take()) {
+                self.push(record);
+            }
+        }
+    }
+
+    /// Hands every message the console hasn't seen yet to it, if there is a console.
+    fn flush(&mut self) {
+        if !console::has_sinks() {
+            return;
+        }
+        // messages that got overwritten before a console showed up are gone for good
+        let start = self.unprinted.max(self.oldest());
+        for seq in start..self.next {
+            let record = self.get(seq);
+            console::write(record.level, format_args!("{record}\n"));
+        }
+        self.unprinted = self.next;
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ModuleFilter {
+    prefix: [u8; MAX_FILTER_LEN],
+    len: usize,
+    level: Level,
+}
+
+impl ModuleFilter {
+    fn prefix(&self) -> &[u8] {
+        &self.prefix[..self.len]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterError {
+    TooManyFilters,
+    /// The module path is longer than 64 bytes.
+    TooLong,
+}
+
+/// Records a message from `module` and prints it to the console, unless it's filtered out.
+///
+/// Use the [`error!`], [`warn!`], [`info!`], [`debug!`] and [`trace!`] macros instead of calling
+/// this directly.
+pub fn log(level: Level, module: &'static str, args: fmt::Arguments<'_>) {
+    if level > level_for(module) {
+        return;
+    }
+    let timestamp = time::uptime_ns();
+    let mut record = Record {
+        timestamp,
+        date: time::wall_clock_at(timestamp),
+        level,
+        module,
+        ..Record::EMPTY
+    };
+    let _ = fmt::Write::write_fmt(
+        &mut TextWriter {
+            record: &mut record,
+        },
+        args,
+    );
+
+    let Some(mut ring) = RING.try_lock() else {
+        park(record);
+        return;
+    };
+    ring.take_overflow();
+    ring.push(record);
+    ring.flush();
+}
+
+/// Logs `value` with one record per line, for multi-line output like register dumps.
+pub fn log_lines(level: Level, module: &'static str, value: impl fmt::Display) {
+    let mut writer = LineWriter {
+        level,
+        module,
+        line: [0; MAX_MESSAGE_LEN],
+        len: 0,
+    };
+    let _ = fmt::Write::write_fmt(&mut writer, format_args!("{value}"));
+    if writer.len > 0 {
+        writer.emit();
+    }
+}
+
+/// Collects a line and logs it once it's complete, what doesn't fit into a record is dropped.
+struct LineWriter {
+    level: Level,
+    module: &'static str,
+    line: [u8; MAX_MESSAGE_LEN],
+    len: usize,
+}
+
+impl LineWriter {
+    fn emit(&mut self) {
+        let line = core::str::from_utf8(&self.line[..self.len]).unwrap_or("<invalid utf-8>");
+        log(self.level, self.module, format_args!("{line}"));
+        self.len = 0;
+    }
+}
+
+impl fmt::Write for LineWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (i, part) in s.split('\n').enumerate() {
+            if i > 0 {
+                self.emit();
+            }
+            let mut end = part.len().min(MAX_MESSAGE_LEN - self.len);
+            while !part.is_char_boundary(end) {
+                end -= 1;
+            }
+            self.line[self.len..self.len + end].copy_from_slice(&part.as_bytes()[..end]);
+            self.len += end;
+        }
+        Ok(())
+    }
+}
+
+/// Keeps `record` in a free overflow slot until the ring is unlocked again.
+fn park(record: Record) {
+    for slot in &OVERFLOW {
+        if let Some(mut slot) = slot.try_lock() {
+            if slot.is_none() {
+                *slot = Some(record);
+                return;
+            }
+        }
+    }
+    DROPPED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Prints the messages logged before the first console sink was registered.
+pub fn flush() {
+    if let Some(mut ring) = RING.try_lock() {
+        ring.take_overflow();
+        ring.flush();
+    }
+}
+
+/// Prints the whole ring, like `dmesg`.
+pub fn dump() {
+    let ring = RING.lock();
+    for seq in ring.oldest()..ring.next {
+        let record = ring.get(seq);
+        console::write(Level::Error, format_args!("{record}\n"));
+    }
+    let dropped = DROPPED.load(Ordering::Relaxed);
+    if dropped > 0 {
+        console::write(
+            Level::Error,
+            format_args!("({dropped} messages dropped, the log was busy and the overflow full)\n"),
+        );
+    }
+}
+
+/// Only keeps messages of modules whose path starts with `module` if they are at least as
+/// severe as `level`. The longest matching prefix wins, modules without a filter use the default
+/// level.
+pub fn set_module_level(module: &str, level: Level) -> Result<(), FilterError> {
+    if module.len() > MAX_FILTER_LEN {
+        return Err(FilterError::TooLong);
+    }
+    let mut filters = FILTERS.lock();
+    if let Some(filter) = filters
+        .iter_mut()
+        .flatten()
+        .find(|filter| filter.prefix() == module.as_bytes())
+    {
+        filter.level = level;
+        return Ok(());
+    }
+    let slot = filters
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or(FilterError::TooManyFilters)?;
+    let mut prefix = [0; MAX_FILTER_LEN];
+    prefix[..module.len()].copy_from_slice(module.as_bytes());
+    *slot = Some(ModuleFilter {
+        prefix,
+        len: module.len(),
+        level,
+    });
+    Ok(())
+}
+
+pub fn set_default_level(level: Level) {
+    DEFAULT_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+fn level_for(module: &str) -> Level {
+    let default = Level::from_u8(DEFAULT_LEVEL.load(Ordering::Relaxed));
+    let Some(filters) = FILTERS.try_lock() else {
+        return default;
+    };
+    filters
+        .iter()
+        .flatten()
+        .filter(|filter| module.as_bytes().starts_with(filter.prefix()))
+        .max_by_key(|filter| filter.len)
+        .map_or(default, |filter| filter.level)
+}
@@ -3,6 +3,9 @@
 #![feature(abi_x86_interrupt)]
 #![feature(allocator_api)]
 #![feature(strict_provenance)]
+#![feature(custom_test_frameworks)]
+#![test_runner(crate::testing::runner)]
+#![reexport_test_harness_main = "test_main"]
 
 extern crate alloc;
 
@@ -15,24 +18,36 @@ use limine::BaseRevision;
 use limine::framebuffer::Framebuffer;
 use limine::paging::Mode;
 use limine::request::{
-    FramebufferRequest, HhdmRequest, MemoryMapRequest, PagingModeRequest, StackSizeRequest,
+    FramebufferRequest, HhdmRequest, MemoryMapRequest, PagingModeRequest, RsdpRequest,
+    StackSizeRequest,
 };
 use spin::Mutex;
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use yashima_core::bit_utils::BitRange;
+use yashima_core::mem::bitmap::{Bitmap, create_bitmap};
+use yashima_core::mem::bootstrap_allocator;
+use yashima_core::mem::bootstrap_allocator::BootstrapAllocator;
+use yashima_core::paging;
+use yashima_core::paging::VirtAddr;
 
 use fontmodule::char_buffer::{CharBuffer, Color};
 use fontmodule::font;
 
-use crate::arch::x86_64::paging::PhysAddr;
-use crate::bit_utils::BitRange;
-use crate::mem::bitmap::{Bitmap, create_bitmap};
-use crate::mem::bootstrap_allocator::BootstrapAllocator;
-use crate::mem::KernelAlloc;
+use crate::arch::x86_64::{apic, exceptions, gdt, ioapic, pic, qemu};
+use crate::console::Level;
+use crate::mem::vmalloc::{Backing, VmPerms};
+use crate::mem::{frame_meta, vmalloc, KernelAlloc};
 
+mod acpi;
 mod arch;
-mod bit_utils;
+mod console;
+mod debug;
+mod drivers;
 mod fontmodule;
+mod klog;
 mod mem;
+#[cfg(test)]
+mod testing;
+mod time;
 
 #[used]
 static BASE_REVISION: BaseRevision = BaseRevision::new();
@@ -54,54 +69,81 @@ pub static STACK_SIZE_REQUEST: StackSizeRequest = StackSizeRequest::new().with_s
 #[used]
 pub static MEMORY_MAP_REQUEST: MemoryMapRequest = MemoryMapRequest::new();
 
+#[used]
+static RSDP_REQUEST: RsdpRequest = RsdpRequest::new();
+
 #[no_mangle]
-pub extern "C" fn memcpy(dst: *mut u8, src: *const u8, n: usize) {
+pub extern "C" fn memcpy(dst: *mut u8, src: *const u8, n: usize) -> *mut u8 {
     for i in 0..n {
         unsafe {
             *dst.add(i) = *src.add(i);
         }
     }
+    dst
 }
 
-static mut dummy_bitmap: [u8; 0] = [];
 // TODO
 // this is a crime. might as well just use rawpointers for the vec to avoud getting into nasty type
 // issues later on but i just wanna get on at this point
 // this all is just a fugazy, just a trick, to get the Allocator type out of the kernel allocator
 // struct. this way i can allocate the bitmap first via the bootstrap allocator and then have the
 // bitmap managed by the kernel allocator later itself by coping the contents into it.
-const HEAP_START: u64 = 0xfffff80000000000;
+const HEAP_START: VirtAddr = VirtAddr::new(0xfffff80000000000);
+// the heap is backed lazily, so reserving a lot of it up front costs nothing
+const HEAP_SIZE: u64 = 1 << 32;
 static mut permanentn_bitmap: Option<Vec<u8, BootstrapAllocator>> = None;
 #[global_allocator]
-static mut K_ALLOC: KernelAlloc = unsafe {
-    KernelAlloc {
-        heap_adr: HEAP_START,
-        bitmap: Bitmap(&mut dummy_bitmap),
-    }
+static K_ALLOC: KernelAlloc = KernelAlloc {
+    heap_adr: HEAP_START,
 };
 
+// the compiler emits calls to these with the C signatures, e.g. for `ptr::write_bytes`, so the
+// argument order has to match libc exactly.
 #[no_mangle]
-pub extern "C" fn memcmp(
-    a: *const u8,
-    a_len: usize,
-    b: *const u8,
-    b_len: usize,
-) -> core::cmp::Ordering {
-    let a_slice = unsafe { core::slice::from_raw_parts(a, a_len) };
-    let b_slice = unsafe { core::slice::from_raw_parts(b, b_len) };
-    a_slice.cmp(&b_slice)
+pub extern "C" fn memcmp(a: *const u8, b: *const u8, n: usize) -> i32 {
+    for i in 0..n {
+        let (a, b) = unsafe { (*a.add(i), *b.add(i)) };
+        if a != b {
+            return a as i32 - b as i32;
+        }
+    }
+    0
+}
+
+// `copy_within` and friends call this for overlapping copies
+#[no_mangle]
+pub extern "C" fn memmove(dst: *mut u8, src: *const u8, n: usize) -> *mut u8 {
+    if (dst as *const u8) < src {
+        for i in 0..n {
+            unsafe {
+                *dst.add(i) = *src.add(i);
+            }
+        }
+    } else {
+        for i in (0..n).rev() {
+            unsafe {
+                *dst.add(i) = *src.add(i);
+            }
+        }
+    }
+    dst
 }
 
 #[no_mangle]
-pub extern "C" fn memset(slice: *mut u8, slice_len: usize, value: u8) {
-    let slice = unsafe { core::slice::from_raw_parts_mut(slice, slice_len) };
-    for element in slice {
-        *element = value;
+pub extern "C" fn memset(dst: *mut u8, value: i32, n: usize) -> *mut u8 {
+    for i in 0..n {
+        unsafe {
+            *dst.add(i) = value as u8;
+        }
     }
+    dst
 }
 
 #[no_mangle]
 pub extern "C" fn main() -> ! {
+    // every conversion between physical and virtual addresses depends on it
+    paging::set_hhdm_offset(HHDM_REQUEST.get_response().unwrap().offset());
+    init_console();
     unsafe {
         core::ptr::read_volatile(STACK_SIZE_REQUEST.get_response().unwrap());
         let mmap = MEMORY_MAP_REQUEST.get_response().unwrap();
@@ -109,94 +151,98 @@ pub extern "C" fn main() -> ! {
         let hhdm_offset = HHDM_REQUEST.get_response().unwrap();
 
         let entries = mmap.entries();
+        debug::symbols::init();
 
-        let a = 0;
-        let ptr_a: *const usize = &a;
-        println!(" a1 {:x?} ", ptr_a);
-
-        let b = 0;
-        let ptr_b: *const usize = &b;
-        println!(" b1 {:x?} ", ptr_b);
-
-        if ptr_b.cmp(&ptr_a).is_lt() {
-            println!(" downwards ");
-        } else {
-            println!(" downwards ");
-        }
-
-        // stackcheck(ptr_a);
         {
-            let b_alloc = mem::bootstrap_allocator::init_bootstrap_alloc(mmap, hhdm_offset);
+            let b_alloc = bootstrap_allocator::init_bootstrap_alloc(entries, hhdm_offset.offset());
+            let b_heap_start = VirtAddr::from_ptr(b_alloc.start()).to_phys().unwrap();
+            let b_heap_size = b_alloc.size() as u64;
             let mut bitmap_vec = create_bitmap(mmap.entries(), b_alloc);
+            debug!("bitmap size: {}", bitmap_vec.len());
+
+            let mut bitmap = Bitmap(permanentn_bitmap.insert(bitmap_vec));
+            // the bootstrap heap lies in usable memory, so the bitmap itself thinks it's free
+            bitmap.mark_range_used(b_heap_start, b_heap_size);
+            mem::init_frame_allocator(bitmap);
+        }
 
-            // let bitmap = Bitmap::new(&mut bitmap_vec);
-            K_ALLOC.bitmap.0 = permanentn_bitmap.insert(bitmap_vec);
+        // the IDT entries reference our kernel code selector, so the GDT has to come first
+        gdt::init();
+        exceptions::init();
+        // every line stays masked until a driver asks for its interrupt
+        pic::init();
+        arch::x86_64::enable_interrupts();
+        // not inline, the lock would be held while `frame_meta::init` allocates frames
+        let frame_count = mem::frame_allocator().0.len() * 8;
+        frame_meta::init(frame_count);
+        vmalloc::map_fixed_region("heap", HEAP_START, HEAP_SIZE, VmPerms::WRITE, Backing::Lazy)
+            .expect("couldn't reserve the heap");
+        mem::heap::init(HEAP_START, HEAP_SIZE);
+        match RSDP_REQUEST.get_response() {
+            Some(rsdp) => acpi::init(rsdp.address()),
+            None => warn!("no rsdp from the bootloader, running without acpi"),
         }
+        time::init();
+        // the xAPIC and IOAPIC registers get mapped with ioremap, so this waits for vmalloc
+        match apic::init() {
+            Ok(()) => match ioapic::init() {
+                Ok(()) => pic::disable(),
+                Err(e) => warn!("device interrupts stay with the pics: {e:?}"),
+            },
+            Err(e) => warn!("no local apic, staying with the pics: {e:?}"),
+        }
+        drivers::init_interrupts();
 
-        let page = K_ALLOC.bitmap.find_free_4kb_page();
+        let page = mem::frame_allocator().find_free_4kb_page();
         match page {
             None => {
-                println!("no page found");
+                warn!("no page found");
             }
             Some(page) => {
-                println!("page: {:?}", page);
+                info!("page: {:?}", page);
             }
         }
     }
-    loop {}
-}
+    // the tests need the heap, the clock and the interrupt controllers, so they run last
+    #[cfg(test)]
+    test_main();
 
-fn stackcheck(ptra: *const usize) {
-    let a = 0;
-
-    let ptr_a: *const usize = &a;
-
-    let ptr_x = ptr_a as usize - ptra as usize;
-
-    println!(" a2 {:x?} ", ptr_a);
-    println!(" x: {:x?}", ptr_x);
-}
-
-unsafe fn resolve_hhdm<T>(addr: &PhysAddr, hhdm_offset: u64) -> &T {
-    let virt_ptr = addr.raw_mut::<u8>().offset(hhdm_offset as isize);
-
-    let r = virt_ptr as *mut T;
-    &(*r)
+    console::shell::run();
 }
 
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     println!("{:?}", info);
+    debug::backtrace::print();
+    if qemu::is_present() {
+        // scripted runs should see the failure instead of waiting for a timeout
+        qemu::exit(qemu::ExitCode::Failed);
+    }
     loop {}
 }
 
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    loop {}
-}
-
-extern "x86-interrupt" fn err_code(stack_frame: InterruptStackFrame, err_code: u64) {
-    println!("err");
-    loop {}
-}
-
-extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
-    error_code: PageFaultErrorCode,
-) {
-    println!("pg");
-    loop {}
-}
-
-extern "x86-interrupt" fn double_fault_handler(
-    stack_frame: InterruptStackFrame,
-    error_code: u64,
-) -> ! {
-    println!("df");
-    loop {}
+#[cfg(test)]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    testing::panicked(info)
 }
 
-pub fn init_idt() {
-    IDT.load();
+/// Registers every output device we have with the console.
+fn init_console() {
+    // serial and debugcon need nothing else, so they are up first to capture as much as possible
+    if drivers::serial::COM1.lock().init(115200).is_ok() {
+        let _ = console::register("serial", &drivers::serial::COM1, Level::Debug);
+    }
+    if qemu::is_present() {
+        let _ = console::register("debugcon", &qemu::DEBUGCON, Level::Trace);
+    }
+    let has_framebuffer = FRAMEBUFFER_REQUEST
+        .get_response()
+        .is_some_and(|response| response.framebuffers().next().is_some());
+    if has_framebuffer {
+        let _ = console::register("framebuffer", &*CHARBUFFER, Level::Info);
+    }
 }
 
 lazy_static! {
@@ -214,18 +260,3 @@ lazy_static! {
     };
 }
 
-lazy_static! {
-    static ref IDT: InterruptDescriptorTable = {
-        let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
-        idt.general_protection_fault.set_handler_fn(err_code);
-        idt.page_fault.set_handler_fn(page_fault_handler);
-        idt.device_not_available.set_handler_fn(breakpoint_handler);
-        idt.alignment_check.set_handler_fn(err_code);
-        idt.security_exception.set_handler_fn(err_code);
-        idt.bound_range_exceeded.set_handler_fn(breakpoint_handler);
-        idt.cp_protection_exception.set_handler_fn(err_code);
-        idt.double_fault.set_handler_fn(double_fault_handler);
-        idt
-    };
-}
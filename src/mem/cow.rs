@@ -0,0 +1,144 @@
+use yashima_core::mem::page::{Page, PageSize};
+use yashima_core::mem::PageFrameAllocator;
+use yashima_core::paging::{PTEntry, PTFlags, VirtAddr};
+
+use crate::arch::x86_64::mapper;
+use crate::arch::x86_64::mapper::MapError;
+use crate::mem::vmalloc::FaultError;
+use crate::mem::{frame_allocator, frame_meta, try_frame_allocator};
+
+/// Maps the frame behind `src` a second time at `dst` and shares it copy on write.
+///
+/// Writable pages lose their write access in both mappings and get the [`PTFlags::COW`] bit, the
+/// first write to either of them faults and is resolved by [`handle_write_fault`]. Read-only pages
+/// are simply shared.
+///
+/// # Safety
+///
+/// `dst` must not be mapped and no mutable references into `src` may be alive.
+pub unsafe fn share_page(src: VirtAddr, dst: VirtAddr) -> Result<(), MapError> {
+    let entry = match mapper::entry_mut(src) {
+        Some(entry) if entry.is_present() => entry,
+        _ => return Err(MapError::NotMapped),
+    };
+    let frame = entry.get_phys_addr();
+    let mut flags = entry.get_flags().unwrap();
+    if flags.contains(PTFlags::RW) {
+        flags.remove(PTFlags::RW);
+        flags.insert(PTFlags::COW);
+        *entry = PTEntry::new(frame, flags);
+        mapper::flush_tlb(src);
+    }
+
+    mapper::map_page(dst, frame, flags, &mut *frame_allocator())?;
+    if let Some(meta) = frame_meta::get(frame) {
+        meta.share();
+    }
+    Ok(())
+}
+
+/// Resolves a write to a present copy on write page at `addr`.
+///
+/// While the frame is still shared the page gets a private copy, otherwise the last mapping
+/// simply becomes writable again. Returns false if the page is not copy on write (or no frame is
+/// left for the copy), in which case the fault is a real protection violation.
+///
+/// # Safety
+///
+/// Has to be called from the page fault handler for a write to a present page.
+pub unsafe fn handle_write_fault(addr: VirtAddr) -> Result<bool, FaultError> {
+    let page = addr.align_down(PageSize::KB4 as u64);
+    let Some(entry) = mapper::entry_mut(page) else {
+        return Ok(false);
+    };
+    let Some(mut flags) = entry.get_flags() else {
+        return Ok(false);
+    };
+    if !flags.contains(PTFlags::P | PTFlags::COW) {
+        return Ok(false);
+    }
+    let frame = entry.get_phys_addr();
+    flags.remove(PTFlags::COW);
+    flags.insert(PTFlags::RW);
+
+    let shared = frame_meta::get(frame).is_some_and(|meta| meta.refcount() > 1);
+    if !shared {
+        *entry = PTEntry::new(frame, flags);
+        mapper::flush_tlb(page);
+        return Ok(true);
+    }
+
+    let mut frames = try_frame_allocator().ok_or(FaultError::FramesLocked)?;
+    let Some(copy) = frames.allocate_frame() else {
+        return Ok(false);
+    };
+    core::ptr::copy_nonoverlapping(
+        frame.to_virt().as_ptr::<u8>(),
+        copy.start.to_virt().as_mut_ptr::<u8>(),
+        PageSize::KB4 as usize,
+    );
+    *entry = PTEntry::new(copy.start, flags);
+    mapper::flush_tlb(page);
+    if frame_meta::release(frame) {
+        frames.deallocate_frame(Page::new(frame, PageSize::KB4));
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::vmalloc;
+    use crate::mem::vmalloc::VmPerms;
+
+    const KB4: u64 = PageSize::KB4 as u64;
+
+    fn flags(page: VirtAddr) -> PTFlags {
+        unsafe { mapper::entry_mut(page).unwrap().get_flags().unwrap() }
+    }
+
+    #[test_case]
+    fn shared_pages_are_copied_on_write() {
+        let src = vmalloc::vmalloc("cow src", KB4, VmPerms::WRITE).unwrap();
+        // the lazy region is never touched, it only provides an unmapped page for the copy
+        let dst = vmalloc::vmalloc_lazy("cow dst", KB4, VmPerms::WRITE).unwrap();
+        let src_ptr = src.as_mut_ptr::<u8>();
+        let dst_ptr = dst.as_mut_ptr::<u8>();
+        unsafe { src_ptr.write_bytes(0x11, KB4 as usize) };
+        let frame = mapper::translate(src).unwrap();
+
+        unsafe { share_page(src, dst).unwrap() };
+        assert_eq!(mapper::translate(dst), Some(frame));
+        for page in [src, dst] {
+            assert!(!flags(page).contains(PTFlags::RW));
+            assert!(flags(page).contains(PTFlags::COW));
+        }
+        assert_eq!(frame_meta::get(frame).unwrap().refcount(), 2);
+
+        // the write faults and gets a private copy, the source keeps the old bytes
+        unsafe { dst_ptr.write_volatile(0x22) };
+        let copy = mapper::translate(dst).unwrap();
+        assert_ne!(copy, frame);
+        assert!(flags(dst).contains(PTFlags::RW));
+        assert!(!flags(dst).contains(PTFlags::COW));
+        unsafe {
+            assert_eq!(dst_ptr.read_volatile(), 0x22);
+            assert_eq!(dst_ptr.add(1).read_volatile(), 0x11);
+            assert_eq!(src_ptr.read_volatile(), 0x11);
+        }
+        // the source is the only mapping left, the frame is owned again
+        assert_eq!(frame_meta::get(frame).unwrap().refcount(), 0);
+
+        // so its write fault makes the page writable without copying
+        unsafe { src_ptr.write_volatile(0x33) };
+        assert_eq!(mapper::translate(src), Some(frame));
+        assert!(flags(src).contains(PTFlags::RW));
+        assert!(!flags(src).contains(PTFlags::COW));
+        unsafe {
+            assert_eq!(src_ptr.read_volatile(), 0x33);
+            assert_eq!(dst_ptr.read_volatile(), 0x22);
+            vmalloc::vfree(dst).unwrap();
+            vmalloc::vfree(src).unwrap();
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use core::mem::size_of;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use spin::Once;
+use yashima_core::mem::page::PageSize;
+use yashima_core::paging::PhysAddr;
+
+use crate::mem::vmalloc;
+use crate::mem::vmalloc::VmPerms;
+
+static FRAME_META: Once<&'static [FrameMeta]> = Once::new();
+
+/// Bookkeeping for a single physical frame.
+///
+/// An all zero `FrameMeta` is valid, so the array can live in freshly zeroed frames.
+#[repr(C)]
+pub struct FrameMeta {
+    /// Number of mappings sharing the frame. 0 means the frame is not shared and belongs to
+    /// whoever allocated it.
+    refcount: AtomicU32,
+}
+
+impl FrameMeta {
+    pub fn refcount(&self) -> u32 {
+        self.refcount.load(Ordering::Acquire)
+    }
+
+    /// Accounts for one more mapping of the frame.
+    pub fn share(&self) {
+        // an unshared frame already has its owner, sharing it makes two mappings
+        let _ = self
+            .refcount
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                Some(if count == 0 { 2 } else { count + 1 })
+            });
+    }
+
+    /// Drops one mapping of the frame. Returns true if it was the last one, i.e. the frame may be
+    /// freed.
+    pub fn unshare(&self) -> bool {
+        let previous = self
+            .refcount
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                // with a single mapping left the frame is owned again
+                Some(if count <= 2 { 0 } else { count - 1 })
+            })
+            .unwrap();
+        previous <= 1
+    }
+}
+
+/// Allocates the metadata for `frame_count` frames, starting at frame 0.
+pub fn init(frame_count: usize) {
+    FRAME_META.call_once(|| {
+        let size = (frame_count * size_of::<FrameMeta>()) as u64;
+        let start = vmalloc::vmalloc("frame meta", size, VmPerms::WRITE)
+            .expect("couldn't allocate the frame metadata");
+        // vmalloc hands out zeroed frames, which is a valid FrameMeta
+        unsafe { core::slice::from_raw_parts(start.as_ptr::<FrameMeta>(), frame_count) }
+    });
+}
+
+/// Metadata of the frame starting at `frame`.
+pub fn get(frame: PhysAddr) -> Option<&'static FrameMeta> {
+    FRAME_META
+        .get()?
+        .get((frame.as_u64() / PageSize::KB4 as u64) as usize)
+}
+
+/// Drops one mapping of `frame`. Returns true if no other mapping references it anymore, in
+/// which case the caller should give it back to the frame allocator.
+pub fn release(frame: PhysAddr) -> bool {
+    match get(frame) {
+        Some(meta) => meta.unshare(),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn refcount_follows_the_mappings() {
+        let meta = FrameMeta {
+            refcount: AtomicU32::new(0),
+        };
+        meta.share();
+        assert_eq!(meta.refcount(), 2);
+        meta.share();
+        assert_eq!(meta.refcount(), 3);
+        assert!(!meta.unshare());
+        assert_eq!(meta.refcount(), 2);
+        // the last mapping owns the frame again
+        assert!(!meta.unshare());
+        assert_eq!(meta.refcount(), 0);
+        assert!(meta.unshare());
+    }
+}
@@ -0,0 +1,149 @@
+// The kernel heap, a first fit allocator on top of the lazily backed heap region. The free blocks
+// form a list sorted by address, every free block keeps its size and the next free block in its
+// own first bytes. Only the pages the heap hands out or keeps headers in get backed by frames.
+
+use core::alloc::Layout;
+use core::ptr;
+
+use spin::Mutex;
+use yashima_core::paging::VirtAddr;
+
+/// Blocks are aligned to and a multiple of this, so every free block has room for its header.
+const BLOCK_ALIGN: usize = 16;
+
+static HEAP: Mutex<Heap> = Mutex::new(Heap {
+    free: ptr::null_mut(),
+});
+
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+struct Heap {
+    /// The free block with the lowest address.
+    free: *mut FreeBlock,
+}
+
+unsafe impl Send for Heap {}
+
+/// Hands `start..start + size` to the heap.
+///
+/// # Safety
+///
+/// The range has to be mapped, lazily is fine, writable and not used by anything else. It can only
+/// be given to the heap once.
+pub unsafe fn init(start: VirtAddr, size: u64) {
+    let start = start.align_up(BLOCK_ALIGN as u64);
+    let block = start.as_mut_ptr::<FreeBlock>();
+    block.write(FreeBlock {
+        size: size as usize & !(BLOCK_ALIGN - 1),
+        next: ptr::null_mut(),
+    });
+    HEAP.lock().free = block;
+}
+
+/// Allocates a block for `layout`, null if the heap is exhausted or not set up yet.
+///
+/// Must not be called from interrupt handlers, the code they interrupt may hold the heap.
+pub fn alloc(layout: Layout) -> *mut u8 {
+    let (size, align) = block_layout(layout);
+    unsafe { HEAP.lock().allocate(size, align) }
+}
+
+/// Gives a block back to the heap.
+///
+/// # Safety
+///
+/// `ptr` has to come from [`alloc`] with the same `layout`.
+pub unsafe fn dealloc(ptr: *mut u8, layout: Layout) {
+    let (size, _) = block_layout(layout);
+    HEAP.lock().free(ptr as usize, size);
+}
+
+fn block_layout(layout: Layout) -> (usize, usize) {
+    let size = layout.size().max(1).next_multiple_of(BLOCK_ALIGN);
+    (size, layout.align().max(BLOCK_ALIGN))
+}
+
+impl Heap {
+    unsafe fn allocate(&mut self, size: usize, align: usize) -> *mut u8 {
+        // the link pointing to the block we look at
+        let mut link: *mut *mut FreeBlock = &mut self.free;
+        while !(*link).is_null() {
+            let block = *link;
+            let start = block as usize;
+            let end = start + (*block).size;
+            let addr = start.next_multiple_of(align);
+            if addr + size > end {
+                link = &mut (*block).next;
+                continue;
+            }
+
+            let next = (*block).next;
+            // the padding for the alignment stays free, it is a multiple of BLOCK_ALIGN as well
+            if addr > start {
+                (*block).size = addr - start;
+                link = &mut (*block).next;
+            }
+            *link = if addr + size < end {
+                let rest = (addr + size) as *mut FreeBlock;
+                rest.write(FreeBlock {
+                    size: end - addr - size,
+                    next,
+                });
+                rest
+            } else {
+                next
+            };
+            return addr as *mut u8;
+        }
+        ptr::null_mut()
+    }
+
+    unsafe fn free(&mut self, addr: usize, size: usize) {
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut next = self.free;
+        while !next.is_null() && (next as usize) < addr {
+            prev = next;
+            next = (*next).next;
+        }
+
+        let block = addr as *mut FreeBlock;
+        block.write(FreeBlock { size, next });
+        // merge with the neighbours, so the free blocks don't fragment forever
+        if !next.is_null() && addr + size == next as usize {
+            (*block).size += (*next).size;
+            (*block).next = (*next).next;
+        }
+        if prev.is_null() {
+            self.free = block;
+        } else if prev as usize + (*prev).size == addr {
+            (*prev).size += (*block).size;
+            (*prev).next = (*block).next;
+        } else {
+            (*prev).next = block;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn allocations_are_aligned_and_reused() {
+        let layout = Layout::from_size_align(1000, 4096).unwrap();
+        let first = alloc(layout);
+        assert!(!first.is_null());
+        assert_eq!(first as usize % 4096, 0);
+        unsafe {
+            first.write_bytes(0xaa, layout.size());
+            dealloc(first, layout);
+        }
+        // first fit, nothing below the freed block was large enough the first time either
+        let second = alloc(layout);
+        assert_eq!(first, second);
+        unsafe { dealloc(second, layout) };
+    }
+}
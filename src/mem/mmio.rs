@@ -0,0 +1,134 @@
+use core::mem::size_of;
+
+use yashima_core::mem::page::PageSize;
+use yashima_core::paging::{PTFlags, PhysAddr, VirtAddr};
+
+use crate::error;
+use crate::mem::vmalloc;
+use crate::mem::vmalloc::{Backing, VmPerms};
+
+const KB4: u64 = PageSize::KB4 as u64;
+
+/// Memory type of an MMIO mapping.
+///
+/// The types are selected via the PWT and PCD bits of the page table entry and therefore rely on
+/// the PAT holding its power on defaults (WB, WT, UC-, UC for the first four entries).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheType {
+    WriteBack,
+    WriteThrough,
+    /// UC-. Uncacheable, but can be overridden to write combining by the MTRRs.
+    UncachedMinus,
+    /// Strong uncacheable. This is what device registers usually want.
+    Uncacheable,
+}
+
+impl CacheType {
+    fn flags(&self) -> PTFlags {
+        match self {
+            CacheType::WriteBack => PTFlags::empty(),
+            CacheType::WriteThrough => PTFlags::PWT,
+            CacheType::UncachedMinus => PTFlags::PCD,
+            CacheType::Uncacheable => PTFlags::PCD | PTFlags::PWT,
+        }
+    }
+}
+
+/// Maps the physical range `phys..phys + len` into a fresh vmalloc region.
+///
+/// The hhdm only covers the RAM listed in the memory map, device registers (APIC, HPET, PCIe
+/// config space) usually lie outside of it. `phys` does not need to be page aligned, the returned
+/// region starts exactly at `phys`.
+///
+/// # Safety
+///
+/// `phys` has to be device memory (or memory that is otherwise not managed by the frame
+/// allocator) since the mapping is writable and bypasses rust's aliasing rules. The frame
+/// allocator has to be initialized.
+pub unsafe fn ioremap(phys: u64, len: usize, cache_type: CacheType) -> MmioRegion {
+    assert!(len > 0, "ioremap of an empty range");
+    let page_offset = phys % KB4;
+    let backing = Backing::Mmio(PhysAddr::new(phys).align_down(KB4), cache_type.flags());
+    let region = vmalloc::map_region("ioremap", page_offset + len as u64, VmPerms::WRITE, backing)
+        .unwrap_or_else(|e| panic!("ioremap of {phys:#x} failed: {e:?}"));
+
+    MmioRegion {
+        base: (region.start + page_offset).as_mut_ptr(),
+        phys,
+        len,
+    }
+}
+
+/// A range of device memory mapped by [`ioremap`].
+///
+/// All accesses are volatile and bounds checked against the mapped length. The mapping is removed
+/// when the region is dropped.
+#[derive(Debug)]
+pub struct MmioRegion {
+    base: *mut u8,
+    phys: u64,
+    len: usize,
+}
+
+unsafe impl Send for MmioRegion {}
+
+unsafe impl Sync for MmioRegion {}
+
+impl MmioRegion {
+    pub fn base(&self) -> *mut u8 {
+        self.base
+    }
+
+    pub fn phys_addr(&self) -> u64 {
+        self.phys
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn read32(&self, offset: usize) -> u32 {
+        unsafe { self.ptr::<u32>(offset).read_volatile() }
+    }
+
+    pub fn write32(&self, offset: usize, value: u32) {
+        unsafe { self.ptr::<u32>(offset).write_volatile(value) }
+    }
+
+    pub fn read64(&self, offset: usize) -> u64 {
+        unsafe { self.ptr::<u64>(offset).read_volatile() }
+    }
+
+    pub fn write64(&self, offset: usize, value: u64) {
+        unsafe { self.ptr::<u64>(offset).write_volatile(value) }
+    }
+
+    /// Pointer to a `T` at `offset` bytes into the region.
+    ///
+    /// Panics if the access is out of bounds or not naturally aligned, since device registers
+    /// are not required to support split accesses.
+    fn ptr<T>(&self, offset: usize) -> *mut T {
+        assert!(
+            offset + size_of::<T>() <= self.len,
+            "mmio access at {offset:#x} out of bounds for region of {:#x} bytes",
+            self.len
+        );
+        let ptr = unsafe { self.base.add(offset) } as *mut T;
+        assert!(ptr.is_aligned(), "unaligned mmio access");
+        ptr
+    }
+}
+
+impl Drop for MmioRegion {
+    fn drop(&mut self) {
+        let region_start = VirtAddr::from_ptr(self.base).align_down(KB4);
+        // panicking in drop helps nobody, the mapping just stays around
+        if let Err(e) = unsafe { vmalloc::vfree(region_start) } {
+            error!("leaking the mapping of mmio {:#x}: {e:?}", self.phys);
+        }
+    }
+}
@@ -1,45 +1,47 @@
 use core::alloc::{GlobalAlloc, Layout};
 
-use limine::memory_map;
-
-use crate::mem::bitmap::Bitmap;
-use crate::mem::page::Page;
-use crate::print;
-
-pub mod bitmap;
-pub mod bootstrap_allocator;
+use spin::{Mutex, MutexGuard};
+use yashima_core::mem::bitmap::Bitmap;
+use yashima_core::paging::VirtAddr;
+
+pub mod cow;
+pub mod frame_meta;
+pub mod heap;
+pub mod mmio;
+pub mod vmalloc;
 pub(crate) mod page;
 
-pub struct KernelAlloc<'a> {
-    pub heap_adr: u64,
-    pub bitmap: Bitmap<'a>,
+static FRAMES: Mutex<Bitmap<'static>> = Mutex::new(Bitmap(&mut []));
+
+pub struct KernelAlloc {
+    pub heap_adr: VirtAddr,
 }
 
-unsafe impl<'a> GlobalAlloc for KernelAlloc<'a> {
+unsafe impl GlobalAlloc for KernelAlloc {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        todo!()
+        heap::alloc(layout)
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        todo!()
+        heap::dealloc(ptr, layout)
     }
 }
 
-pub trait PageFrameAllocator {
-    fn allocate_frame(&mut self) -> Option<Page>;
+/// Hands the bitmap `main` built from the memory map to the frame allocator.
+pub fn init_frame_allocator(bitmap: Bitmap<'static>) {
+    *FRAMES.lock() = bitmap;
+}
 
-    fn deallocate_frame(&mut self);
+/// Locks the physical frame allocator of the kernel. Until [`init_frame_allocator`] it has no
+/// frames.
+///
+/// The page fault handler has to use [`try_frame_allocator`], the fault may have interrupted the
+/// code holding the lock.
+pub fn frame_allocator() -> MutexGuard<'static, Bitmap<'static>> {
+    FRAMES.lock()
 }
 
-fn calc_mem_available(entries: &[&memory_map::Entry]) -> u64 {
-    let mut max_base_addr = 0;
-    let mut length_of_entry = 0;
-    for entry in entries {
-        if entry.base > max_base_addr {
-            max_base_addr = entry.base;
-            length_of_entry = entry.length;
-        }
-    }
-    print!("max addr : {} ", max_base_addr + length_of_entry);
-    max_base_addr + length_of_entry
+/// [`frame_allocator`] for the page fault handler, `None` if the allocator is locked.
+pub fn try_frame_allocator() -> Option<MutexGuard<'static, Bitmap<'static>>> {
+    FRAMES.try_lock()
 }
@@ -0,0 +1,445 @@
+use core::fmt;
+
+use bitflags::bitflags;
+use spin::Mutex;
+use yashima_core::mem::page::{Page, PageSize};
+use yashima_core::mem::PageFrameAllocator;
+use yashima_core::paging::{PTFlags, PhysAddr, VirtAddr};
+
+use crate::arch::x86_64::mapper;
+use crate::arch::x86_64::mapper::MapError;
+use crate::mem::{frame_allocator, frame_meta, try_frame_allocator};
+use crate::println;
+
+/// Start of the kernel virtual range regions are handed out from.
+///
+/// The range spans the whole PML4 slot 500, above the heap at [`crate::HEAP_START`].
+pub const VMALLOC_START: VirtAddr = VirtAddr::new(0xfffffa0000000000);
+pub const VMALLOC_SIZE: u64 = 1 << 39;
+
+/// Unmapped gap that is kept below and above every region, so running off the end of a stack or
+/// buffer faults instead of silently corrupting the neighbour.
+pub const GUARD_SIZE: u64 = KB4;
+
+const KB4: u64 = PageSize::KB4 as u64;
+const MAX_REGIONS: usize = 128;
+
+static REGIONS: Mutex<RegionList> = Mutex::new(RegionList::new());
+
+bitflags! {
+    /// Access rights of a region. Regions are always readable.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct VmPerms: u8 {
+        const WRITE = 1 << 0;
+        const EXEC = 1 << 1;
+    }
+}
+
+impl VmPerms {
+    fn pt_flags(&self) -> PTFlags {
+        let mut flags = PTFlags::G;
+        if self.contains(VmPerms::WRITE) {
+            flags |= PTFlags::RW;
+        }
+        if !self.contains(VmPerms::EXEC) {
+            flags |= PTFlags::NX;
+        }
+        flags
+    }
+}
+
+/// What lies behind the pages of a region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backing {
+    /// Every page got its own frame from the frame allocator when the region was created. The
+    /// frames are not physically contiguous.
+    Frames,
+    /// Pages get a zeroed frame from the page fault handler the first time they are touched.
+    /// Reserving a lazy region therefore costs no physical memory.
+    Lazy,
+    /// Device memory starting at the given physical address, mapped with the given extra page
+    /// table flags (caching). The frames are not owned by the region.
+    Mmio(PhysAddr, PTFlags),
+}
+
+#[derive(Debug)]
+pub enum VmError {
+    /// No gap in the vmalloc range is large enough.
+    OutOfVirtualSpace,
+    /// The region list is full.
+    TooManyRegions,
+    /// No region starts at the given address.
+    NoSuchRegion,
+    /// Regions of 0 bytes are not allowed.
+    EmptyRegion,
+    Map(MapError),
+}
+
+impl From<MapError> for VmError {
+    fn from(e: MapError) -> Self {
+        VmError::Map(e)
+    }
+}
+
+/// A named range of kernel virtual memory.
+#[derive(Debug, Clone, Copy)]
+pub struct VmRegion {
+    pub name: &'static str,
+    pub start: VirtAddr,
+    /// Size in bytes, always a multiple of 4Kb. Guard gaps are not included.
+    pub size: u64,
+    pub perms: VmPerms,
+    pub backing: Backing,
+}
+
+impl VmRegion {
+    /// First address after the region.
+    pub fn end(&self) -> VirtAddr {
+        self.start + self.size
+    }
+
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end()
+    }
+}
+
+impl fmt::Display for VmRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let write = if self.perms.contains(VmPerms::WRITE) {
+            'w'
+        } else {
+            '-'
+        };
+        let exec = if self.perms.contains(VmPerms::EXEC) {
+            'x'
+        } else {
+            '-'
+        };
+        write!(
+            f,
+            "{:#x}-{:#x} {:>8}K r{}{} ",
+            self.start.as_u64(),
+            self.end().as_u64(),
+            self.size / 1024,
+            write,
+            exec
+        )?;
+        match self.backing {
+            Backing::Frames => write!(f, "frames ")?,
+            Backing::Lazy => write!(f, "lazy ")?,
+            Backing::Mmio(phys, _) => write!(f, "mmio@{:#x} ", phys.as_u64())?,
+        }
+        write!(f, "{}", self.name)
+    }
+}
+
+/// Regions sorted by their start address.
+struct RegionList {
+    regions: [Option<VmRegion>; MAX_REGIONS],
+    len: usize,
+}
+
+impl RegionList {
+    const fn new() -> Self {
+        RegionList {
+            regions: [None; MAX_REGIONS],
+            len: 0,
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &VmRegion> {
+        self.regions[..self.len].iter().flatten()
+    }
+
+    /// First fit search for a gap of `size` bytes with a guard gap on either side.
+    fn find_gap(&self, size: u64) -> Option<VirtAddr> {
+        let mut candidate = VMALLOC_START + GUARD_SIZE;
+        // fixed regions outside of the window (like the heap) never influence the search
+        for region in self.iter().filter(|r| r.start >= VMALLOC_START) {
+            if region.start > candidate && region.start - candidate >= size + GUARD_SIZE {
+                return Some(candidate);
+            }
+            candidate = region.end() + GUARD_SIZE;
+        }
+        let window_end = VMALLOC_START.as_u64() + VMALLOC_SIZE;
+        if candidate.as_u64() + size + GUARD_SIZE <= window_end {
+            Some(candidate)
+        } else {
+            None
+        }
+    }
+
+    fn insert(&mut self, region: VmRegion) -> Result<(), VmError> {
+        if self.len == MAX_REGIONS {
+            return Err(VmError::TooManyRegions);
+        }
+        let index = self
+            .iter()
+            .position(|r| r.start > region.start)
+            .unwrap_or(self.len);
+        self.regions.copy_within(index..self.len, index + 1);
+        self.regions[index] = Some(region);
+        self.len += 1;
+        Ok(())
+    }
+
+    fn remove(&mut self, start: VirtAddr) -> Option<VmRegion> {
+        let index = self.iter().position(|r| r.start == start)?;
+        let region = self.regions[index].take();
+        self.regions.copy_within(index + 1..self.len, index);
+        self.len -= 1;
+        self.regions[self.len] = None;
+        region
+    }
+}
+
+/// Reserves `size` bytes (rounded up to 4Kb) of kernel virtual memory and backs every page with
+/// its own zeroed frame.
+pub fn vmalloc(name: &'static str, size: u64, perms: VmPerms) -> Result<VirtAddr, VmError> {
+    map_region(name, size, perms, Backing::Frames).map(|region| region.start)
+}
+
+/// Reserves `size` bytes (rounded up to 4Kb) of kernel virtual memory that is backed on first
+/// touch, see [`Backing::Lazy`].
+pub fn vmalloc_lazy(name: &'static str, size: u64, perms: VmPerms) -> Result<VirtAddr, VmError> {
+    map_region(name, size, perms, Backing::Lazy).map(|region| region.start)
+}
+
+/// Creates a region and maps its pages according to `backing`.
+pub fn map_region(
+    name: &'static str,
+    size: u64,
+    perms: VmPerms,
+    backing: Backing,
+) -> Result<VmRegion, VmError> {
+    if size == 0 {
+        return Err(VmError::EmptyRegion);
+    }
+    let size = size.div_ceil(KB4) * KB4;
+    let region = {
+        let mut regions = REGIONS.lock();
+        let start = regions.find_gap(size).ok_or(VmError::OutOfVirtualSpace)?;
+        let region = VmRegion {
+            name,
+            start,
+            size,
+            perms,
+            backing,
+        };
+        regions.insert(region)?;
+        region
+    };
+    populate_or_remove(region)
+}
+
+/// Registers a region at a fixed address outside of the vmalloc range, e.g. the heap.
+///
+/// The caller is responsible for `start..start + size` not overlapping anything else.
+pub fn map_fixed_region(
+    name: &'static str,
+    start: VirtAddr,
+    size: u64,
+    perms: VmPerms,
+    backing: Backing,
+) -> Result<VmRegion, VmError> {
+    assert!(
+        start.is_aligned(KB4),
+        "fixed regions have to be page aligned"
+    );
+    if size == 0 {
+        return Err(VmError::EmptyRegion);
+    }
+    let region = VmRegion {
+        name,
+        start,
+        size: size.div_ceil(KB4) * KB4,
+        perms,
+        backing,
+    };
+    REGIONS.lock().insert(region)?;
+    populate_or_remove(region)
+}
+
+fn populate_or_remove(region: VmRegion) -> Result<VmRegion, VmError> {
+    if let Err(e) = unsafe { populate(&region) } {
+        unsafe { unmap_region(&region) };
+        REGIONS.lock().remove(region.start);
+        return Err(e);
+    }
+    Ok(region)
+}
+
+/// Unmaps the region starting at `start` and returns its frames to the frame allocator.
+///
+/// # Safety
+///
+/// No references into the region may be alive.
+pub unsafe fn vfree(start: VirtAddr) -> Result<(), VmError> {
+    let region = REGIONS.lock().remove(start).ok_or(VmError::NoSuchRegion)?;
+    unmap_region(&region);
+    Ok(())
+}
+
+/// Returns a copy of the region `addr` lies in.
+pub fn find_region(addr: VirtAddr) -> Option<VmRegion> {
+    REGIONS.lock().iter().find(|r| r.contains(addr)).copied()
+}
+
+/// [`find_region`] for the page fault handler.
+///
+/// The fault may have interrupted code holding the region list, waiting for it would never end.
+/// That is reported as [`FaultError::RegionsLocked`], nothing may touch lazy memory while holding
+/// the list.
+pub fn find_fault_region(addr: VirtAddr) -> Result<VmRegion, FaultError> {
+    let regions = REGIONS.try_lock().ok_or(FaultError::RegionsLocked)?;
+    let region = regions.iter().find(|r| r.contains(addr));
+    region.copied().ok_or(FaultError::NoRegion)
+}
+
+/// Why a page fault could not be resolved by [`handle_page_fault`].
+#[derive(Debug, Clone, Copy)]
+pub enum FaultError {
+    /// The address does not belong to any region (or hits a guard gap).
+    NoRegion,
+    /// The region is not lazily backed, so the page should have been present.
+    NotLazy(VmRegion),
+    /// The access is not allowed by the permissions of the region.
+    AccessViolation(VmRegion),
+    OutOfMemory(VmRegion),
+    /// Mapping the new frame failed.
+    Map(VmRegion, MapError),
+    /// The fault happened while the region list was locked.
+    RegionsLocked,
+    /// The fault happened while the frame allocator was locked.
+    FramesLocked,
+}
+
+/// Tries to resolve a not present fault at `addr` by backing the page with a zeroed frame.
+///
+/// `write` and `instruction_fetch` describe the faulting access and are checked against the
+/// permissions of the region. On success the faulting instruction can simply be restarted.
+///
+/// # Safety
+///
+/// Has to be called from the page fault handler for a fault caused by a not present page.
+pub unsafe fn handle_page_fault(
+    addr: VirtAddr,
+    write: bool,
+    instruction_fetch: bool,
+) -> Result<(), FaultError> {
+    let region = find_fault_region(addr)?;
+    if region.backing != Backing::Lazy {
+        return Err(FaultError::NotLazy(region));
+    }
+    if (write && !region.perms.contains(VmPerms::WRITE))
+        || (instruction_fetch && !region.perms.contains(VmPerms::EXEC))
+    {
+        return Err(FaultError::AccessViolation(region));
+    }
+
+    let mut frames = try_frame_allocator().ok_or(FaultError::FramesLocked)?;
+    let frame = frames
+        .allocate_frame()
+        .ok_or(FaultError::OutOfMemory(region))?;
+    core::ptr::write_bytes(frame.start.to_virt().as_mut_ptr::<u8>(), 0, KB4 as usize);
+    let page = addr.align_down(KB4);
+    let flags = region.perms.pt_flags();
+    if let Err(e) = mapper::map_page(page, frame.start, flags, &mut *frames) {
+        frames.deallocate_frame(frame);
+        return Err(FaultError::Map(region, e));
+    }
+    Ok(())
+}
+
+pub fn print_regions() {
+    let regions = REGIONS.lock();
+    println!("vmalloc regions ({} used):", regions.len);
+    for region in regions.iter() {
+        println!("  {region}");
+    }
+}
+
+unsafe fn populate(region: &VmRegion) -> Result<(), VmError> {
+    let flags = region.perms.pt_flags();
+    let mut frames = frame_allocator();
+    for offset in (0..region.size).step_by(KB4 as usize) {
+        let virt = region.start + offset;
+        match region.backing {
+            Backing::Frames => {
+                let frame = frames
+                    .allocate_frame()
+                    .ok_or(VmError::Map(MapError::FrameAllocationFailed))?;
+                core::ptr::write_bytes(frame.start.to_virt().as_mut_ptr::<u8>(), 0, KB4 as usize);
+                if let Err(e) = mapper::map_page(virt, frame.start, flags, &mut *frames) {
+                    frames.deallocate_frame(frame);
+                    return Err(e.into());
+                }
+            }
+            Backing::Mmio(phys, cache_flags) => {
+                mapper::map_page(virt, phys + offset, flags | cache_flags, &mut *frames)?;
+            }
+            Backing::Lazy => return Ok(()),
+        }
+    }
+    Ok(())
+}
+
+unsafe fn unmap_region(region: &VmRegion) {
+    let mut frames = frame_allocator();
+    for offset in (0..region.size).step_by(KB4 as usize) {
+        let Some(frame) = mapper::unmap_page(region.start + offset) else {
+            continue;
+        };
+        let owns_frame = region.backing == Backing::Frames || region.backing == Backing::Lazy;
+        // the frame may still be mapped copy on write somewhere else
+        if owns_frame && frame_meta::release(frame) {
+            frames.deallocate_frame(Page::new(frame, PageSize::KB4));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn lazy_pages_are_backed_on_first_touch() {
+        let start = vmalloc_lazy("lazy test", 2 * KB4, VmPerms::WRITE).unwrap();
+        let second_page = start + KB4;
+        assert!(!mapper::is_mapped(start));
+        assert!(!mapper::is_mapped(second_page));
+
+        // the read faults, gets a zeroed frame and is restarted
+        let ptr = start.as_mut_ptr::<u64>();
+        assert_eq!(unsafe { ptr.read_volatile() }, 0);
+        assert!(mapper::is_mapped(start));
+        unsafe {
+            ptr.add(1).write_volatile(0xdead_beef);
+            assert_eq!(ptr.add(1).read_volatile(), 0xdead_beef);
+        }
+        // only the touched page is backed
+        assert!(!mapper::is_mapped(second_page));
+        unsafe { vfree(start).unwrap() };
+    }
+
+    #[test_case]
+    fn write_to_read_only_lazy_region_is_rejected() {
+        let start = vmalloc_lazy("read only test", KB4, VmPerms::empty()).unwrap();
+        let result = unsafe { handle_page_fault(start, true, false) };
+        assert!(matches!(result, Err(FaultError::AccessViolation(_))));
+        assert!(!mapper::is_mapped(start));
+        // reading is fine
+        assert!(unsafe { handle_page_fault(start, false, false) }.is_ok());
+        unsafe { vfree(start).unwrap() };
+    }
+
+    #[test_case]
+    fn guard_gaps_belong_to_no_region() {
+        let region = map_region("guard test", KB4, VmPerms::WRITE, Backing::Lazy).unwrap();
+        for addr in [region.start - GUARD_SIZE, region.end()] {
+            let result = unsafe { handle_page_fault(addr, false, false) };
+            assert!(matches!(result, Err(FaultError::NoRegion)));
+        }
+        unsafe { vfree(region.start).unwrap() };
+    }
+}
@@ -0,0 +1,114 @@
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use spin::Once;
+
+use crate::arch::x86_64::qemu;
+use crate::arch::x86_64::qemu::ExitCode;
+use crate::{print, println};
+
+static TESTS: Once<&'static [&'static dyn Testable]> = Once::new();
+/// Index of the next test to run.
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+static FAILED: AtomicUsize = AtomicUsize::new(0);
+/// Set by [`expect_panic`] for the running test.
+static EXPECT_PANIC: AtomicBool = AtomicBool::new(false);
+
+/// Anything that can be marked with `#[test_case]`.
+pub trait Testable: Sync {
+    fn run(&self);
+    fn name(&self) -> &'static str;
+}
+
+impl<T: Fn() + Sync> Testable for T {
+    fn run(&self) {
+        self()
+    }
+
+    fn name(&self) -> &'static str {
+        core::any::type_name::<T>()
+    }
+}
+
+/// Marks the running test as expected to panic. It passes if it panics after this call and fails
+/// if it returns.
+pub fn expect_panic() {
+    EXPECT_PANIC.store(true, Ordering::SeqCst);
+}
+
+/// Entry point of the test harness, called by `test_main`.
+///
+/// The results go to the console, QEMU exits with [`ExitCode::Success`] if every test passed.
+pub fn runner(tests: &[&dyn Testable]) {
+    // the harness passes a slice of statics, so it lives for the rest of the run anyway
+    let tests: &'static [&'static dyn Testable] = unsafe { core::mem::transmute(tests) };
+    TESTS.call_once(|| tests);
+    println!("running {} tests", tests.len());
+    run_remaining();
+}
+
+/// Called by the panic handler. A panic ends the running test, the remaining tests run on top of
+/// the panicked stack since there is no unwinding.
+///
+/// Locks the panicking test held stay locked, so a panic inside e.g. a console sink silences
+/// that sink for the rest of the run.
+pub fn panicked(info: &PanicInfo) -> ! {
+    if EXPECT_PANIC.swap(false, Ordering::SeqCst) {
+        println!("[ok]");
+    } else {
+        println!("[failed]");
+        println!("{info}");
+        FAILED.fetch_add(1, Ordering::SeqCst);
+    }
+    if TESTS.get().is_none() {
+        // the kernel panicked before the tests even started
+        qemu::exit(ExitCode::Failed);
+    }
+    run_remaining();
+}
+
+fn run_remaining() -> ! {
+    let tests = TESTS.get().unwrap();
+    loop {
+        let index = NEXT.fetch_add(1, Ordering::SeqCst);
+        let Some(test) = tests.get(index) else {
+            break;
+        };
+        print!("{} ... ", test.name());
+        EXPECT_PANIC.store(false, Ordering::SeqCst);
+        test.run();
+        if EXPECT_PANIC.swap(false, Ordering::SeqCst) {
+            println!("[failed] (should have panicked)");
+            FAILED.fetch_add(1, Ordering::SeqCst);
+        } else {
+            println!("[ok]");
+        }
+    }
+
+    let failed = FAILED.load(Ordering::SeqCst);
+    println!("{} passed, {failed} failed", tests.len() - failed);
+    if failed == 0 {
+        qemu::exit(ExitCode::Success);
+    }
+    qemu::exit(ExitCode::Failed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static PANICKED: AtomicBool = AtomicBool::new(false);
+
+    // the harness orders the tests by name, this one has to run first
+    #[test_case]
+    fn expected_panic_ends_the_test() {
+        expect_panic();
+        PANICKED.store(true, Ordering::SeqCst);
+        panic!("expected");
+    }
+
+    #[test_case]
+    fn expected_panic_is_followed_by_the_next_test() {
+        assert!(PANICKED.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,257 @@
+// The monotonic system clock. It runs on the best counter we have: an invariant TSC calibrated
+// against the HPET or the PIT, else the HPET itself, and as the last resort channel 0 of the PIT.
+// The wall clock is the monotonic clock plus the date the RTC had when we read it. The counters
+// drift against the RTC, so the wall clock checks back with it every minute.
+
+use core::hint::spin_loop;
+use core::ops::{Add, Sub};
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use spin::Mutex;
+use yashima_core::time::{DateTime, Monotonic, NANOS_PER_SEC};
+
+use crate::arch::x86_64::without_interrupts;
+use crate::drivers::{hpet, pit, rtc};
+use crate::{debug, info, warn};
+
+pub mod tsc;
+
+static CLOCK: Mutex<Option<Clock>> = Mutex::new(None);
+/// Nanoseconds since the UNIX epoch at uptime 0, see [`sync_wall_clock`].
+static BOOT_TIME_NS: AtomicU64 = AtomicU64::new(0);
+/// The uptime of the last look at the RTC, 0 before [`sync_wall_clock`].
+static LAST_SYNC_NS: AtomicU64 = AtomicU64::new(0);
+
+const RESYNC_INTERVAL_NS: u64 = 60 * NANOS_PER_SEC;
+
+/// A free running counter that can drive the clock.
+pub trait Clocksource: Sync {
+    fn name(&self) -> &'static str;
+    /// Ticks per second.
+    fn frequency(&self) -> u64;
+    /// The implemented bits of the counter, it wraps after `mask + 1` ticks.
+    fn mask(&self) -> u64;
+    fn read(&self) -> u64;
+}
+
+struct Clock {
+    source: &'static dyn Clocksource,
+    time: Monotonic,
+}
+
+/// A point in time since boot, as measured by the monotonic clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub fn now() -> Instant {
+        Instant(uptime_ns())
+    }
+
+    /// The time that went by since `earlier`, zero if `earlier` is later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(earlier.0))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    /// Nanoseconds since boot.
+    pub fn as_nanos(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        Instant(self.0 + rhs.as_nanos() as u64)
+    }
+}
+
+impl Sub for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+/// Starts the clock on the PIT and moves it to the HPET or the TSC if they are usable, then sets
+/// the wall clock from the RTC.
+///
+/// The HPET is found through the ACPI tables and mapped with ioremap, so this waits for both.
+pub fn init() {
+    pit::start_counter();
+    set_clocksource(&pit::COUNTER);
+
+    let reference: &'static dyn Clocksource = match hpet::init() {
+        Ok(hpet) => hpet,
+        Err(e) => {
+            warn!("no hpet, the pit is the reference clock: {e:?}");
+            &pit::COUNTER
+        }
+    };
+    match tsc::init(reference) {
+        Ok(tsc) => set_clocksource(tsc),
+        Err(e) => {
+            info!("not using the tsc: {e:?}");
+            set_clocksource(reference);
+        }
+    }
+    sync_wall_clock();
+}
+
+/// Drives the clock with `source` from now on, the time carries on where the old one left it.
+pub fn set_clocksource(source: &'static dyn Clocksource) {
+    without_interrupts(|| {
+        let mut clock = CLOCK.lock();
+        let now = source.read();
+        match clock.as_mut() {
+            Some(clock) => {
+                clock.time.update(clock.source.read());
+                clock.time.switch(source.frequency(), source.mask(), now);
+                clock.source = source;
+            }
+            None => {
+                *clock = Some(Clock {
+                    source,
+                    time: Monotonic::new(source.frequency(), source.mask(), now),
+                })
+            }
+        }
+    });
+    info!(
+        "clocksource: {}, {} kHz",
+        source.name(),
+        source.frequency() / 1000
+    );
+}
+
+/// The name of the counter driving the clock, `None` before [`init`].
+pub fn clocksource_name() -> Option<&'static str> {
+    without_interrupts(|| CLOCK.lock().as_ref().map(|clock| clock.source.name()))
+}
+
+pub fn is_initialized() -> bool {
+    without_interrupts(|| CLOCK.lock().is_some())
+}
+
+/// Nanoseconds since the clock started, 0 before [`init`].
+///
+/// The PIT wraps every 55ms. While it drives the clock, the time only stays right if something
+/// reads it at least that often.
+pub fn uptime_ns() -> u64 {
+    // the log reads the clock, also from interrupt handlers
+    without_interrupts(|| match CLOCK.lock().as_mut() {
+        Some(clock) => clock.time.update(clock.source.read()),
+        None => 0,
+    })
+}
+
+/// Sets the wall clock to the date of the RTC. Until the next sync it advances with the monotonic
+/// clock, which is far more precise than the seconds of the RTC.
+pub fn sync_wall_clock() {
+    let date = rtc::read();
+    let uptime = uptime_ns();
+    let now = date.unix_timestamp() * NANOS_PER_SEC;
+    BOOT_TIME_NS.store(now.saturating_sub(uptime), Ordering::Relaxed);
+    LAST_SYNC_NS.store(uptime, Ordering::Relaxed);
+    info!("wall clock: {date} UTC");
+}
+
+/// Seconds since the UNIX epoch. Before [`sync_wall_clock`] the epoch is the boot.
+///
+/// Once a minute this compares the wall clock with the RTC, which can take up to 2ms.
+pub fn wall_clock_now() -> u64 {
+    let uptime = uptime_ns();
+    let last_sync = LAST_SYNC_NS.load(Ordering::Relaxed);
+    // whoever wins the exchange resyncs, an interrupt handler calling in meanwhile doesn't. One
+    // that resynced between the two loads leaves a last sync after `uptime`.
+    if last_sync != 0
+        && uptime.saturating_sub(last_sync) >= RESYNC_INTERVAL_NS
+        && LAST_SYNC_NS
+            .compare_exchange(last_sync, uptime, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+    {
+        resync_wall_clock();
+    }
+    (BOOT_TIME_NS.load(Ordering::Relaxed) + uptime_ns()) / NANOS_PER_SEC
+}
+
+/// Seconds since the UNIX epoch at `uptime_ns`, `None` before [`sync_wall_clock`].
+///
+/// Unlike [`wall_clock_now`] this never looks at the RTC, the log stamps its messages with it.
+pub fn wall_clock_at(uptime_ns: u64) -> Option<u64> {
+    if LAST_SYNC_NS.load(Ordering::Relaxed) == 0 {
+        return None;
+    }
+    Some((BOOT_TIME_NS.load(Ordering::Relaxed) + uptime_ns) / NANOS_PER_SEC)
+}
+
+/// Moves the wall clock to the RTC if the two drifted apart by more than a second.
+///
+/// The RTC only tells the second we are in, adjusting to it every time would make the wall clock
+/// jitter back and forth by up to a second.
+fn resync_wall_clock() {
+    let rtc_secs = rtc::read().unix_timestamp();
+    let uptime = uptime_ns();
+    let secs = (BOOT_TIME_NS.load(Ordering::Relaxed) + uptime) / NANOS_PER_SEC;
+    if rtc_secs.abs_diff(secs) > 1 {
+        let boot_time = (rtc_secs * NANOS_PER_SEC).saturating_sub(uptime);
+        BOOT_TIME_NS.store(boot_time, Ordering::Relaxed);
+        debug!("wall clock was {secs}, the rtc says {rtc_secs}");
+    }
+}
+
+/// The date of [`wall_clock_now`], in UTC.
+pub fn date_now() -> DateTime {
+    DateTime::from_unix(wall_clock_now())
+}
+
+/// Busy waits `us` microseconds.
+pub fn udelay(us: u64) {
+    delay(Duration::from_micros(us));
+}
+
+/// Busy waits `ms` milliseconds.
+pub fn mdelay(ms: u64) {
+    delay(Duration::from_millis(ms));
+}
+
+fn delay(duration: Duration) {
+    assert!(is_initialized(), "delay before the clock runs");
+    let deadline = Instant::now() + duration;
+    while Instant::now() < deadline {
+        spin_loop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn wall_clock_follows_the_monotonic_clock() {
+        let before = wall_clock_now();
+        // the RTC of QEMU starts at the date of the host
+        assert!(date_now().year >= 2024);
+        mdelay(1100);
+        assert!(wall_clock_now() > before);
+    }
+
+    #[test_case]
+    fn mdelay_takes_as_long_as_asked() {
+        let start = Instant::now();
+        mdelay(20);
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(20));
+        assert!(
+            elapsed < Duration::from_millis(100),
+            "mdelay(20) took {elapsed:?}"
+        );
+    }
+}
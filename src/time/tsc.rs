@@ -0,0 +1,93 @@
+// The time stamp counter. It is the cheapest counter to read, but only an invariant TSC ticks at
+// a constant rate through frequency and power state changes. Its rate isn't reported anywhere we
+// could rely on, so it is measured against a reference clocksource.
+
+use spin::Once;
+use yashima_core::bit;
+
+use crate::arch::x86_64::cpuid::CpuId;
+use crate::arch::x86_64::msr::rdtsc;
+use crate::arch::x86_64::without_interrupts;
+use crate::time::Clocksource;
+use crate::{debug, info};
+
+/// How long the TSC is counted against the reference.
+const CALIBRATION_MS: u64 = 50;
+
+const CPUID_MAX_EXTENDED: u64 = 0x8000_0000;
+const CPUID_POWER_MANAGEMENT: u64 = 0x8000_0007;
+const CPUID_EDX_INVARIANT_TSC: u64 = bit!(8);
+
+static TSC: Once<Tsc> = Once::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TscError {
+    /// The rate of the TSC can change, it is useless as a clock.
+    NotInvariant,
+}
+
+pub struct Tsc {
+    frequency: u64,
+}
+
+impl Clocksource for Tsc {
+    fn name(&self) -> &'static str {
+        "tsc"
+    }
+
+    fn frequency(&self) -> u64 {
+        self.frequency
+    }
+
+    fn mask(&self) -> u64 {
+        u64::MAX
+    }
+
+    fn read(&self) -> u64 {
+        rdtsc()
+    }
+}
+
+/// Checks that the TSC is invariant and measures its frequency against `reference`.
+pub fn init(reference: &dyn Clocksource) -> Result<&'static Tsc, TscError> {
+    if !is_invariant() {
+        return Err(TscError::NotInvariant);
+    }
+    let frequency = calibrate(reference);
+    info!(
+        "tsc: invariant, {} MHz against the {}",
+        frequency / 1_000_000,
+        reference.name()
+    );
+    Ok(TSC.call_once(|| Tsc { frequency }))
+}
+
+/// The TSC frequency in Hz, `None` if it isn't used as a clocksource.
+pub fn frequency() -> Option<u64> {
+    TSC.get().map(|tsc| tsc.frequency)
+}
+
+fn is_invariant() -> bool {
+    let max_extended = CpuId::get_cpuid_eax(CPUID_MAX_EXTENDED).eax;
+    max_extended >= CPUID_POWER_MANAGEMENT
+        && CpuId::get_cpuid_eax(CPUID_POWER_MANAGEMENT).edx & CPUID_EDX_INVARIANT_TSC != 0
+}
+
+/// Counts the TSC ticks while `reference` counts [`CALIBRATION_MS`].
+fn calibrate(reference: &dyn Clocksource) -> u64 {
+    let target = reference.frequency() * CALIBRATION_MS / 1000;
+    let (ticks, elapsed) = without_interrupts(|| {
+        let mut elapsed = 0;
+        let mut last = reference.read();
+        let start = rdtsc();
+        // summing up every step, the pit wraps within the calibration
+        while elapsed < target {
+            let now = reference.read();
+            elapsed += now.wrapping_sub(last) & reference.mask();
+            last = now;
+        }
+        (rdtsc() - start, elapsed)
+    });
+    debug!("tsc: {ticks} ticks in {elapsed} {} ticks", reference.name());
+    (ticks as u128 * reference.frequency() as u128 / elapsed as u128) as u64
+}
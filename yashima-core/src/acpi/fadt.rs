@@ -0,0 +1,229 @@
+// The fixed ACPI description table, it describes the fixed hardware of the platform: the PM
+// timer, the SCI, the reset register and which legacy devices are present.
+
+use bitflags::bitflags;
+
+use crate::acpi::{
+    read_u16, read_u32, read_u64, require_len, validate_table, AcpiError, GenericAddress,
+};
+use crate::bit;
+
+// the FADT grew with every ACPI revision, everything past the end of a shorter table is absent
+const ACPI_1_LENGTH: usize = 116;
+const RESET_VALUE_END: usize = 129;
+const X_DSDT_END: usize = 148;
+const X_PM_TIMER_END: usize = 220;
+
+bitflags! {
+    /// IA-PC boot architecture flags, reserved in ACPI 1.0 FADTs.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct BootArchFlags: u16 {
+        /// There are ISA devices that aren't described in the DSDT.
+        const LEGACY_DEVICES = bit!(0);
+        /// There is an 8042 keyboard controller.
+        const PS2_CONTROLLER = bit!(1);
+        const VGA_NOT_PRESENT = bit!(2);
+        const MSI_NOT_SUPPORTED = bit!(3);
+        const PCIE_ASPM_CONTROLS = bit!(4);
+        const CMOS_RTC_NOT_PRESENT = bit!(5);
+    }
+}
+
+bitflags! {
+    /// Fixed feature flags, only the ones we care about.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct FadtFlags: u32 {
+        /// The PM timer counts with 32 instead of 24 bits.
+        const TMR_VAL_EXT = bit!(8);
+        const RESET_REG_SUP = bit!(10);
+        /// The platform has no legacy hardware, only the hardware-reduced ACPI interface.
+        const HW_REDUCED_ACPI = bit!(20);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fadt {
+    pub revision: u8,
+    /// Physical address of the DSDT, the 64 bit field is preferred if it is set.
+    pub dsdt_address: u64,
+    /// The ISA interrupt (on PCs) the SCI is wired to.
+    pub sci_interrupt: u16,
+    pub smi_command_port: u32,
+    pub pm_timer: Option<PmTimer>,
+    /// CMOS RAM index of the century register of the RTC, if it has one.
+    pub century_register: Option<u8>,
+    pub boot_arch: BootArchFlags,
+    pub flags: FadtFlags,
+    pub reset: Option<ResetRegister>,
+}
+
+/// The ACPI power management timer, a counter running at 3.579545 MHz.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PmTimer {
+    pub address: GenericAddress,
+    /// 32 bit counter instead of 24 bit.
+    pub extended: bool,
+}
+
+/// Writing `value` to `address` resets the system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResetRegister {
+    pub address: GenericAddress,
+    pub value: u8,
+}
+
+impl Fadt {
+    pub fn parse(bytes: &[u8]) -> Result<Self, AcpiError> {
+        let table = validate_table(bytes, b"FACP")?;
+        require_len(table, ACPI_1_LENGTH)?;
+        let len = table.len();
+
+        let flags = FadtFlags::from_bits_retain(read_u32(table, 112));
+        let x_dsdt = if len >= X_DSDT_END {
+            read_u64(table, 140)
+        } else {
+            0
+        };
+        let dsdt_address = if x_dsdt != 0 {
+            x_dsdt
+        } else {
+            read_u32(table, 40) as u64
+        };
+
+        let x_pm_timer = (len >= X_PM_TIMER_END)
+            .then(|| GenericAddress::parse(table, 208))
+            .filter(|address| address.address != 0);
+        // the 32 bit block is an I/O port
+        let pm_timer_port = read_u32(table, 76);
+        let pm_timer_address = match x_pm_timer {
+            Some(address) => Some(address),
+            None if pm_timer_port != 0 => Some(GenericAddress {
+                address_space: GenericAddress::SYSTEM_IO,
+                bit_width: 32,
+                bit_offset: 0,
+                access_size: 3,
+                address: pm_timer_port as u64,
+            }),
+            None => None,
+        };
+
+        let reset =
+            (flags.contains(FadtFlags::RESET_REG_SUP) && len >= RESET_VALUE_END).then(|| {
+                ResetRegister {
+                    address: GenericAddress::parse(table, 116),
+                    value: table[128],
+                }
+            });
+
+        Ok(Self {
+            revision: table[8],
+            dsdt_address,
+            sci_interrupt: read_u16(table, 46),
+            smi_command_port: read_u32(table, 48),
+            pm_timer: pm_timer_address.map(|address| PmTimer {
+                address,
+                extended: flags.contains(FadtFlags::TMR_VAL_EXT),
+            }),
+            century_register: Some(table[108]).filter(|index| *index != 0),
+            boot_arch: BootArchFlags::from_bits_truncate(read_u16(table, 109)),
+            flags,
+            reset,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acpi::build_table;
+
+    /// An FADT body of `len` bytes with a PM timer at port 0x608, the century at CMOS index 0x32
+    /// and, if it fits, the reset register at port 0xcf9.
+    fn fadt_body(len: usize) -> Vec<u8> {
+        let mut body = vec![0; len - 36];
+        let mut put = |offset: usize, bytes: &[u8]| {
+            body[offset - 36..offset - 36 + bytes.len()].copy_from_slice(bytes)
+        };
+        put(40, &0x7fe0040u32.to_le_bytes());
+        put(46, &9u16.to_le_bytes());
+        put(48, &0xb2u32.to_le_bytes());
+        put(76, &0x608u32.to_le_bytes());
+        put(108, &[0x32]);
+        put(109, &(BootArchFlags::PS2_CONTROLLER.bits()).to_le_bytes());
+        let flags = FadtFlags::TMR_VAL_EXT | FadtFlags::RESET_REG_SUP;
+        put(112, &flags.bits().to_le_bytes());
+        if len >= RESET_VALUE_END {
+            put(
+                116,
+                &[
+                    GenericAddress::SYSTEM_IO,
+                    8,
+                    0,
+                    1,
+                    0xf9,
+                    0x0c,
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                    0,
+                ],
+            );
+            put(128, &[0x06]);
+        }
+        body
+    }
+
+    #[test]
+    fn fadt_of_acpi_1() {
+        let fadt = Fadt::parse(&build_table(b"FACP", 1, &fadt_body(ACPI_1_LENGTH))).unwrap();
+        assert_eq!(fadt.dsdt_address, 0x7fe0040);
+        assert_eq!(fadt.sci_interrupt, 9);
+        assert_eq!(fadt.century_register, Some(0x32));
+        assert!(fadt.boot_arch.contains(BootArchFlags::PS2_CONTROLLER));
+        let pm_timer = fadt.pm_timer.unwrap();
+        assert_eq!(pm_timer.address.address_space, GenericAddress::SYSTEM_IO);
+        assert_eq!(pm_timer.address.address, 0x608);
+        assert!(pm_timer.extended);
+        // the flag is set, but the register only came with ACPI 2.0
+        assert_eq!(fadt.reset, None);
+    }
+
+    #[test]
+    fn fadt_prefers_the_64_bit_fields() {
+        let mut body = fadt_body(244);
+        body[140 - 36..148 - 36].copy_from_slice(&0x1_0000_0000u64.to_le_bytes());
+        body[208 - 36..220 - 36].copy_from_slice(&[
+            GenericAddress::SYSTEM_IO,
+            32,
+            0,
+            3,
+            0x08,
+            0xb0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ]);
+        let fadt = Fadt::parse(&build_table(b"FACP", 3, &body)).unwrap();
+        assert_eq!(fadt.dsdt_address, 0x1_0000_0000);
+        assert_eq!(fadt.pm_timer.unwrap().address.address, 0xb008);
+        assert_eq!(fadt.reset.unwrap().value, 0x06);
+        assert_eq!(fadt.reset.unwrap().address.address, 0xcf9);
+    }
+
+    #[test]
+    fn fadt_without_century() {
+        let mut body = fadt_body(ACPI_1_LENGTH);
+        body[108 - 36] = 0;
+        let fadt = Fadt::parse(&build_table(b"FACP", 1, &body)).unwrap();
+        assert_eq!(fadt.century_register, None);
+        assert_eq!(
+            Fadt::parse(&build_table(b"FACP", 1, &body[..40])),
+            Err(AcpiError::Truncated)
+        );
+    }
+}
@@ -0,0 +1,87 @@
+// The HPET description table, it tells where the registers of the high precision event timer
+// are. The timer itself describes the rest in its capabilities register.
+
+use crate::acpi::{
+    read_u16, read_u32, require_len, validate_table, AcpiError, GenericAddress, SdtHeader,
+};
+
+const LENGTH: usize = SdtHeader::SIZE + 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hpet {
+    /// Physical address of the registers.
+    pub base_address: u64,
+    /// Which HPET of the system this is, there usually is just one.
+    pub hpet_number: u8,
+    /// The shortest period in main counter ticks the timer can fire periodically without losing
+    /// interrupts.
+    pub min_tick: u16,
+    pub hardware_revision: u8,
+    pub comparator_count: u8,
+    pub counter_64bit: bool,
+    /// The timer can take over the PIT and RTC interrupts.
+    pub legacy_replacement: bool,
+    pub pci_vendor_id: u16,
+}
+
+impl Hpet {
+    pub fn parse(bytes: &[u8]) -> Result<Self, AcpiError> {
+        let table = validate_table(bytes, b"HPET")?;
+        require_len(table, LENGTH)?;
+        let block_id = read_u32(table, 36);
+        let address = GenericAddress::parse(table, 40);
+        if address.address_space != GenericAddress::SYSTEM_MEMORY {
+            return Err(AcpiError::BadEntry);
+        }
+
+        Ok(Self {
+            base_address: address.address,
+            hpet_number: table[52],
+            min_tick: read_u16(table, 53),
+            hardware_revision: block_id as u8,
+            // the field holds the number of the last comparator
+            comparator_count: (block_id >> 8 & 0x1f) as u8 + 1,
+            counter_64bit: block_id & 1 << 13 != 0,
+            legacy_replacement: block_id & 1 << 15 != 0,
+            pci_vendor_id: (block_id >> 16) as u16,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acpi::build_table;
+
+    #[test]
+    fn hpet_of_qemu() {
+        let mut body = Vec::new();
+        // vendor 8086, legacy replacement, 64 bit, comparators 0-2, revision 1
+        body.extend_from_slice(&0x8086_a201u32.to_le_bytes());
+        body.extend_from_slice(&[GenericAddress::SYSTEM_MEMORY, 0, 0, 0]);
+        body.extend_from_slice(&0xfed0_0000u64.to_le_bytes());
+        body.push(0);
+        body.extend_from_slice(&128u16.to_le_bytes());
+        body.push(0);
+        let hpet = Hpet::parse(&build_table(b"HPET", 1, &body)).unwrap();
+        assert_eq!(
+            hpet,
+            Hpet {
+                base_address: 0xfed0_0000,
+                hpet_number: 0,
+                min_tick: 128,
+                hardware_revision: 1,
+                comparator_count: 3,
+                counter_64bit: true,
+                legacy_replacement: true,
+                pci_vendor_id: 0x8086,
+            }
+        );
+
+        body[4] = GenericAddress::SYSTEM_IO;
+        assert_eq!(
+            Hpet::parse(&build_table(b"HPET", 1, &body)),
+            Err(AcpiError::BadEntry)
+        );
+    }
+}
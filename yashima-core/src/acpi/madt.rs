@@ -0,0 +1,391 @@
+// The multiple APIC description table, it lists the interrupt controllers and how the legacy ISA
+// interrupts are wired to them.
+
+use alloc::vec::Vec;
+
+use crate::acpi::{
+    read_u16, read_u32, read_u64, require_len, validate_table, AcpiError, SdtHeader,
+};
+use crate::bit;
+
+// entry types
+const LOCAL_APIC: u8 = 0;
+const IO_APIC: u8 = 1;
+const INTERRUPT_SOURCE_OVERRIDE: u8 = 2;
+const NMI_SOURCE: u8 = 3;
+const LOCAL_APIC_NMI: u8 = 4;
+const LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+const LOCAL_X2APIC: u8 = 9;
+const LOCAL_X2APIC_NMI: u8 = 10;
+
+/// Flag of the MADT, the system has dual 8259 PICs as well.
+const PCAT_COMPAT: u32 = bit!(0);
+
+/// Processor uid of a local APIC NMI that is connected to every processor.
+const ALL_PROCESSORS: u32 = 0xffff_ffff;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Madt {
+    /// Physical address of the local APIC registers, with a 64 bit override already applied.
+    pub local_apic_address: u64,
+    /// There are legacy PICs that have to be masked before the APICs are used.
+    pub has_legacy_pics: bool,
+    pub local_apics: Vec<LocalApic>,
+    pub io_apics: Vec<IoApic>,
+    pub overrides: Vec<InterruptSourceOverride>,
+    pub nmi_sources: Vec<NmiSource>,
+    pub local_apic_nmis: Vec<LocalApicNmi>,
+}
+
+/// A processor and its local APIC, from either a local APIC or a local x2APIC entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalApic {
+    pub processor_uid: u32,
+    pub apic_id: u32,
+    /// The processor is usable right away.
+    pub enabled: bool,
+    /// The processor is disabled, but can be brought online later.
+    pub online_capable: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApic {
+    pub id: u8,
+    pub address: u32,
+    /// The first global system interrupt this IOAPIC handles.
+    pub gsi_base: u32,
+}
+
+/// An ISA interrupt that isn't identity mapped to a global system interrupt, or doesn't use the
+/// ISA defaults of active high and edge triggered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptSourceOverride {
+    /// Always 0, for ISA.
+    pub bus: u8,
+    pub irq: u8,
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger_mode: TriggerMode,
+}
+
+/// A global system interrupt that is wired up as NMI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NmiSource {
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger_mode: TriggerMode,
+}
+
+/// A LINT pin of local APICs that is wired up as NMI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalApicNmi {
+    /// `None` if the pin is the NMI of every processor.
+    pub processor_uid: Option<u32>,
+    /// LINT0 or LINT1.
+    pub lint: u8,
+    pub polarity: Polarity,
+    pub trigger_mode: TriggerMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    /// Whatever the bus specifies, active high for ISA.
+    ConformsToBus,
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriggerMode {
+    /// Whatever the bus specifies, edge for ISA.
+    ConformsToBus,
+    Edge,
+    Level,
+}
+
+impl Polarity {
+    /// Bits 0-1 of the MPS INTI flags. The reserved value 2 is treated like 0.
+    fn from_flags(flags: u16) -> Self {
+        match flags & 0b11 {
+            0b01 => Polarity::ActiveHigh,
+            0b11 => Polarity::ActiveLow,
+            _ => Polarity::ConformsToBus,
+        }
+    }
+}
+
+impl TriggerMode {
+    /// Bits 2-3 of the MPS INTI flags. The reserved value 2 is treated like 0.
+    fn from_flags(flags: u16) -> Self {
+        match flags >> 2 & 0b11 {
+            0b01 => TriggerMode::Edge,
+            0b11 => TriggerMode::Level,
+            _ => TriggerMode::ConformsToBus,
+        }
+    }
+}
+
+impl Madt {
+    pub fn parse(bytes: &[u8]) -> Result<Self, AcpiError> {
+        let table = validate_table(bytes, b"APIC")?;
+        // local APIC address and flags
+        let entries_start = SdtHeader::SIZE + 8;
+        require_len(table, entries_start)?;
+
+        let mut madt = Madt {
+            local_apic_address: read_u32(table, 36) as u64,
+            has_legacy_pics: read_u32(table, 40) & PCAT_COMPAT != 0,
+            local_apics: Vec::new(),
+            io_apics: Vec::new(),
+            overrides: Vec::new(),
+            nmi_sources: Vec::new(),
+            local_apic_nmis: Vec::new(),
+        };
+
+        let mut offset = entries_start;
+        while offset < table.len() {
+            require_len(table, offset + 2)?;
+            let (kind, length) = (table[offset], table[offset + 1] as usize);
+            if length < 2 {
+                return Err(AcpiError::BadEntry);
+            }
+            require_len(table, offset + length)?;
+            madt.parse_entry(kind, &table[offset..offset + length])?;
+            offset += length;
+        }
+        Ok(madt)
+    }
+
+    /// Adds the entry of type `kind` in `entry`, entries of types we don't use are skipped.
+    fn parse_entry(&mut self, kind: u8, entry: &[u8]) -> Result<(), AcpiError> {
+        let min_length = match kind {
+            LOCAL_APIC => 8,
+            IO_APIC => 12,
+            INTERRUPT_SOURCE_OVERRIDE => 10,
+            NMI_SOURCE => 8,
+            LOCAL_APIC_NMI => 6,
+            LOCAL_APIC_ADDRESS_OVERRIDE => 12,
+            LOCAL_X2APIC => 16,
+            LOCAL_X2APIC_NMI => 12,
+            _ => return Ok(()),
+        };
+        if entry.len() < min_length {
+            return Err(AcpiError::BadEntry);
+        }
+
+        match kind {
+            LOCAL_APIC => self.local_apics.push(LocalApic {
+                processor_uid: entry[2] as u32,
+                apic_id: entry[3] as u32,
+                enabled: read_u32(entry, 4) & 1 != 0,
+                online_capable: read_u32(entry, 4) & 2 != 0,
+            }),
+            IO_APIC => self.io_apics.push(IoApic {
+                id: entry[2],
+                address: read_u32(entry, 4),
+                gsi_base: read_u32(entry, 8),
+            }),
+            INTERRUPT_SOURCE_OVERRIDE => {
+                let flags = read_u16(entry, 8);
+                self.overrides.push(InterruptSourceOverride {
+                    bus: entry[2],
+                    irq: entry[3],
+                    gsi: read_u32(entry, 4),
+                    polarity: Polarity::from_flags(flags),
+                    trigger_mode: TriggerMode::from_flags(flags),
+                });
+            }
+            NMI_SOURCE => {
+                let flags = read_u16(entry, 2);
+                self.nmi_sources.push(NmiSource {
+                    gsi: read_u32(entry, 4),
+                    polarity: Polarity::from_flags(flags),
+                    trigger_mode: TriggerMode::from_flags(flags),
+                });
+            }
+            LOCAL_APIC_NMI => {
+                let flags = read_u16(entry, 3);
+                let uid = match entry[2] {
+                    0xff => ALL_PROCESSORS,
+                    uid => uid as u32,
+                };
+                self.push_local_apic_nmi(uid, entry[5], flags);
+            }
+            LOCAL_APIC_ADDRESS_OVERRIDE => self.local_apic_address = read_u64(entry, 4),
+            LOCAL_X2APIC => self.local_apics.push(LocalApic {
+                processor_uid: read_u32(entry, 12),
+                apic_id: read_u32(entry, 4),
+                enabled: read_u32(entry, 8) & 1 != 0,
+                online_capable: read_u32(entry, 8) & 2 != 0,
+            }),
+            LOCAL_X2APIC_NMI => {
+                self.push_local_apic_nmi(read_u32(entry, 4), entry[8], read_u16(entry, 2));
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn push_local_apic_nmi(&mut self, uid: u32, lint: u8, flags: u16) {
+        self.local_apic_nmis.push(LocalApicNmi {
+            processor_uid: Some(uid).filter(|uid| *uid != ALL_PROCESSORS),
+            lint,
+            polarity: Polarity::from_flags(flags),
+            trigger_mode: TriggerMode::from_flags(flags),
+        });
+    }
+
+    /// The override for the ISA interrupt `irq`, if there is one.
+    pub fn isa_override(&self, irq: u8) -> Option<&InterruptSourceOverride> {
+        self.overrides.iter().find(|o| o.bus == 0 && o.irq == irq)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acpi::build_table;
+
+    /// The MADT QEMU generates for a single processor i440fx machine.
+    fn qemu_madt() -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&0xfee0_0000u32.to_le_bytes());
+        body.extend_from_slice(&PCAT_COMPAT.to_le_bytes());
+        // local APIC 0
+        body.extend_from_slice(&[LOCAL_APIC, 8, 0, 0, 1, 0, 0, 0]);
+        // IOAPIC 0 at 0xfec00000 from GSI 0
+        body.extend_from_slice(&[IO_APIC, 12, 0, 0, 0x00, 0x00, 0xc0, 0xfe, 0, 0, 0, 0]);
+        // PIT on GSI 2
+        body.extend_from_slice(&[INTERRUPT_SOURCE_OVERRIDE, 10, 0, 0, 2, 0, 0, 0, 0, 0]);
+        // ACPI SCI, active high and level triggered
+        body.extend_from_slice(&[INTERRUPT_SOURCE_OVERRIDE, 10, 0, 9, 9, 0, 0, 0, 0x0d, 0]);
+        // LINT1 of all processors is the NMI
+        body.extend_from_slice(&[LOCAL_APIC_NMI, 6, 0xff, 0, 0, 1]);
+        build_table(b"APIC", 1, &body)
+    }
+
+    #[test]
+    fn madt_of_qemu() {
+        let madt = Madt::parse(&qemu_madt()).unwrap();
+        assert_eq!(madt.local_apic_address, 0xfee0_0000);
+        assert!(madt.has_legacy_pics);
+        assert_eq!(
+            madt.local_apics,
+            [LocalApic {
+                processor_uid: 0,
+                apic_id: 0,
+                enabled: true,
+                online_capable: false,
+            }]
+        );
+        assert_eq!(
+            madt.io_apics,
+            [IoApic {
+                id: 0,
+                address: 0xfec0_0000,
+                gsi_base: 0,
+            }]
+        );
+        assert_eq!(madt.overrides.len(), 2);
+        assert_eq!(
+            madt.local_apic_nmis,
+            [LocalApicNmi {
+                processor_uid: None,
+                lint: 1,
+                polarity: Polarity::ConformsToBus,
+                trigger_mode: TriggerMode::ConformsToBus,
+            }]
+        );
+    }
+
+    #[test]
+    fn isa_overrides() {
+        let madt = Madt::parse(&qemu_madt()).unwrap();
+        let pit = madt.isa_override(0).unwrap();
+        assert_eq!(pit.gsi, 2);
+        assert_eq!(pit.trigger_mode, TriggerMode::ConformsToBus);
+        let sci = madt.isa_override(9).unwrap();
+        assert_eq!(sci.polarity, Polarity::ActiveHigh);
+        assert_eq!(sci.trigger_mode, TriggerMode::Level);
+        assert_eq!(madt.isa_override(4), None);
+    }
+
+    #[test]
+    fn x2apic_entries_and_address_override() {
+        let mut body = Vec::new();
+        body.extend_from_slice(&0xfee0_0000u32.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&[LOCAL_APIC_ADDRESS_OVERRIDE, 12, 0, 0]);
+        body.extend_from_slice(&0x1_fee0_0000u64.to_le_bytes());
+        // x2APIC 300 with uid 7, online capable
+        body.extend_from_slice(&[
+            LOCAL_X2APIC,
+            16,
+            0,
+            0,
+            0x2c,
+            0x01,
+            0,
+            0,
+            2,
+            0,
+            0,
+            0,
+            7,
+            0,
+            0,
+            0,
+        ]);
+        // an entry type we don't know is skipped
+        body.extend_from_slice(&[0x7f, 4, 0, 0]);
+        // active low, level triggered LINT0 of uid 7
+        body.extend_from_slice(&[LOCAL_X2APIC_NMI, 12, 0x0f, 0, 7, 0, 0, 0, 0, 0, 0, 0]);
+        let madt = Madt::parse(&build_table(b"APIC", 3, &body)).unwrap();
+
+        assert_eq!(madt.local_apic_address, 0x1_fee0_0000);
+        assert!(!madt.has_legacy_pics);
+        assert_eq!(
+            madt.local_apics,
+            [LocalApic {
+                processor_uid: 7,
+                apic_id: 300,
+                enabled: false,
+                online_capable: true,
+            }]
+        );
+        assert_eq!(
+            madt.local_apic_nmis,
+            [LocalApicNmi {
+                processor_uid: Some(7),
+                lint: 0,
+                polarity: Polarity::ActiveLow,
+                trigger_mode: TriggerMode::Level,
+            }]
+        );
+    }
+
+    #[test]
+    fn broken_entries() {
+        let mut body = vec![0; 8];
+        // a zero length would never advance
+        body.extend_from_slice(&[IO_APIC, 0]);
+        assert_eq!(
+            Madt::parse(&build_table(b"APIC", 1, &body)),
+            Err(AcpiError::BadEntry)
+        );
+
+        let mut body = vec![0; 8];
+        body.extend_from_slice(&[IO_APIC, 12, 0, 0]);
+        assert_eq!(
+            Madt::parse(&build_table(b"APIC", 1, &body)),
+            Err(AcpiError::Truncated)
+        );
+
+        let mut body = vec![0; 8];
+        body.extend_from_slice(&[IO_APIC, 4, 0, 0]);
+        assert_eq!(
+            Madt::parse(&build_table(b"APIC", 1, &body)),
+            Err(AcpiError::BadEntry)
+        );
+    }
+}
@@ -0,0 +1,73 @@
+// The PCI express memory mapped configuration table, it tells where the configuration space of
+// each PCI segment is mapped.
+
+use alloc::vec::Vec;
+
+use crate::acpi::{read_u16, read_u64, require_len, validate_table, AcpiError, SdtHeader};
+
+/// The entries follow 8 reserved bytes after the header.
+const ENTRIES_START: usize = SdtHeader::SIZE + 8;
+const ENTRY_SIZE: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mcfg {
+    pub entries: Vec<McfgEntry>,
+}
+
+/// The configuration space of the buses `start_bus..=end_bus` of a segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct McfgEntry {
+    /// Physical address of the configuration space of bus 0, even if `start_bus` is higher.
+    pub base_address: u64,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+impl McfgEntry {
+    /// Physical address of the 4KiB configuration space of a function.
+    pub fn config_address(&self, bus: u8, device: u8, function: u8) -> u64 {
+        self.base_address + ((bus as u64) << 20 | (device as u64) << 15 | (function as u64) << 12)
+    }
+}
+
+impl Mcfg {
+    pub fn parse(bytes: &[u8]) -> Result<Self, AcpiError> {
+        let table = validate_table(bytes, b"MCFG")?;
+        require_len(table, ENTRIES_START)?;
+        let entries = table[ENTRIES_START..]
+            .chunks_exact(ENTRY_SIZE)
+            .map(|entry| McfgEntry {
+                base_address: read_u64(entry, 0),
+                segment: read_u16(entry, 8),
+                start_bus: entry[10],
+                end_bus: entry[11],
+            })
+            .collect();
+        Ok(Self { entries })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::acpi::build_table;
+
+    #[test]
+    fn mcfg_of_q35() {
+        let mut body = vec![0; 8];
+        body.extend_from_slice(&0xb000_0000u64.to_le_bytes());
+        body.extend_from_slice(&[0, 0, 0, 0xff, 0, 0, 0, 0]);
+        let mcfg = Mcfg::parse(&build_table(b"MCFG", 1, &body)).unwrap();
+        let entry = mcfg.entries[0];
+        assert_eq!(mcfg.entries.len(), 1);
+        assert_eq!(
+            (entry.segment, entry.start_bus, entry.end_bus),
+            (0, 0, 0xff)
+        );
+        assert_eq!(
+            entry.config_address(1, 2, 3),
+            0xb000_0000 + 0x10_0000 + 0x1_0000 + 0x3000
+        );
+    }
+}
@@ -0,0 +1,320 @@
+// Parsers for the ACPI tables the kernel uses. They work on byte slices of the tables, finding
+// and mapping the tables in physical memory is up to the kernel.
+//
+// For the layouts refer to chapter 5.2 "ACPI System Description Tables" of the
+// [ACPI specification](https://uefi.org/specs/ACPI/6.5/05_ACPI_Software_Programming_Model.html).
+
+use alloc::vec::Vec;
+use core::str;
+
+pub mod fadt;
+pub mod hpet;
+pub mod madt;
+pub mod mcfg;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    /// The bytes end before the structure does.
+    Truncated,
+    /// The structure doesn't start with the signature it should.
+    BadSignature,
+    /// The bytes of the structure don't sum up to 0.
+    BadChecksum,
+    /// An entry inside a table claims a length that can't be right.
+    BadEntry,
+}
+
+/// The root system description pointer, the entry point to all other tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rsdp {
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+    pub rsdt_address: u32,
+    /// Only present from revision 2 (ACPI 2.0) on.
+    pub xsdt_address: Option<u64>,
+}
+
+impl Rsdp {
+    const SIGNATURE: &'static [u8; 8] = b"RSD PTR ";
+    /// Size of the revision 2 structure. Passing this many bytes to [`Rsdp::parse`] works for
+    /// every revision.
+    pub const SIZE: usize = 36;
+    /// Size of the revision 0 structure, the part covered by the first checksum.
+    const V1_SIZE: usize = 20;
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, AcpiError> {
+        require_len(bytes, Self::V1_SIZE)?;
+        if &bytes[..8] != Self::SIGNATURE {
+            return Err(AcpiError::BadSignature);
+        }
+        if checksum(&bytes[..Self::V1_SIZE]) != 0 {
+            return Err(AcpiError::BadChecksum);
+        }
+        let revision = bytes[15];
+        let xsdt_address = if revision >= 2 {
+            require_len(bytes, Self::SIZE)?;
+            let length = read_u32(bytes, 20) as usize;
+            require_len(bytes, length)?;
+            if checksum(&bytes[..length]) != 0 {
+                return Err(AcpiError::BadChecksum);
+            }
+            Some(read_u64(bytes, 24)).filter(|addr| *addr != 0)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            revision,
+            oem_id: bytes[9..15].try_into().unwrap(),
+            rsdt_address: read_u32(bytes, 16),
+            xsdt_address,
+        })
+    }
+
+    /// Physical address of the XSDT if there is one, the RSDT otherwise.
+    pub fn root_table_address(&self) -> u64 {
+        self.xsdt_address.unwrap_or(self.rsdt_address as u64)
+    }
+
+    pub fn oem_id(&self) -> &str {
+        as_str(&self.oem_id)
+    }
+}
+
+/// The header every table except the RSDP starts with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    /// Length of the whole table including the header.
+    pub length: u32,
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+impl SdtHeader {
+    pub const SIZE: usize = 36;
+
+    /// Parses the header at the start of `bytes`. Nothing past the header is looked at, so this
+    /// is how the length of a table is found before the whole table is read.
+    pub fn parse(bytes: &[u8]) -> Result<Self, AcpiError> {
+        require_len(bytes, Self::SIZE)?;
+        Ok(Self {
+            signature: bytes[0..4].try_into().unwrap(),
+            length: read_u32(bytes, 4),
+            revision: bytes[8],
+            oem_id: bytes[10..16].try_into().unwrap(),
+            oem_table_id: bytes[16..24].try_into().unwrap(),
+            oem_revision: read_u32(bytes, 24),
+            creator_id: read_u32(bytes, 28),
+            creator_revision: read_u32(bytes, 32),
+        })
+    }
+
+    pub fn signature(&self) -> &str {
+        as_str(&self.signature)
+    }
+
+    pub fn oem_id(&self) -> &str {
+        as_str(&self.oem_id)
+    }
+
+    pub fn oem_table_id(&self) -> &str {
+        as_str(&self.oem_table_id)
+    }
+}
+
+/// An address in one of the ACPI address spaces, e.g. the HPET registers or the FADT reset
+/// register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GenericAddress {
+    /// 0 is system memory, 1 system I/O.
+    pub address_space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+impl GenericAddress {
+    pub const SYSTEM_IO: u8 = 1;
+    pub const SYSTEM_MEMORY: u8 = 0;
+
+    fn parse(bytes: &[u8], offset: usize) -> Self {
+        Self {
+            address_space: bytes[offset],
+            bit_width: bytes[offset + 1],
+            bit_offset: bytes[offset + 2],
+            access_size: bytes[offset + 3],
+            address: read_u64(bytes, offset + 4),
+        }
+    }
+}
+
+/// Checks the header and checksum of the table at the start of `bytes` and returns exactly the
+/// bytes of the table. `bytes` may extend past the end of the table.
+pub fn validate_table<'a>(bytes: &'a [u8], signature: &[u8; 4]) -> Result<&'a [u8], AcpiError> {
+    let header = SdtHeader::parse(bytes)?;
+    if &header.signature != signature {
+        return Err(AcpiError::BadSignature);
+    }
+    let length = header.length as usize;
+    if length < SdtHeader::SIZE {
+        return Err(AcpiError::BadEntry);
+    }
+    require_len(bytes, length)?;
+    let table = &bytes[..length];
+    if checksum(table) != 0 {
+        return Err(AcpiError::BadChecksum);
+    }
+    Ok(table)
+}
+
+/// Physical addresses of the tables listed in an RSDT (32 bit entries) or XSDT (64 bit entries).
+pub fn parse_root_table(bytes: &[u8]) -> Result<Vec<u64>, AcpiError> {
+    let (table, entry_size) = match validate_table(bytes, b"XSDT") {
+        Ok(table) => (table, 8),
+        Err(AcpiError::BadSignature) => (validate_table(bytes, b"RSDT")?, 4),
+        Err(e) => return Err(e),
+    };
+    let entries = table[SdtHeader::SIZE..]
+        .chunks_exact(entry_size)
+        .map(|entry| match entry_size {
+            8 => read_u64(entry, 0),
+            _ => read_u32(entry, 0) as u64,
+        })
+        .collect();
+    Ok(entries)
+}
+
+/// Sum of all bytes, valid structures sum up to 0.
+pub fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn require_len(bytes: &[u8], len: usize) -> Result<(), AcpiError> {
+    if bytes.len() < len {
+        return Err(AcpiError::Truncated);
+    }
+    Ok(())
+}
+
+// the tables are little endian and not aligned, the callers check the length up front
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// The ids are space padded ASCII, anything else is shown as `?`.
+fn as_str(bytes: &[u8]) -> &str {
+    str::from_utf8(bytes).map(|s| s.trim_end()).unwrap_or("?")
+}
+
+/// Builds a table with a valid header and checksum around `body`.
+#[cfg(test)]
+pub(crate) fn build_table(signature: &[u8; 4], revision: u8, body: &[u8]) -> Vec<u8> {
+    let mut table = Vec::new();
+    table.extend_from_slice(signature);
+    table.extend_from_slice(&((SdtHeader::SIZE + body.len()) as u32).to_le_bytes());
+    table.push(revision);
+    table.push(0);
+    table.extend_from_slice(b"YASHMA");
+    table.extend_from_slice(b"TESTTBL ");
+    table.extend_from_slice(&[0; 12]);
+    table.extend_from_slice(body);
+    table[9] = 0u8.wrapping_sub(checksum(&table));
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rsdp(revision: u8, rsdt: u32, xsdt: u64) -> Vec<u8> {
+        let mut rsdp = Vec::new();
+        rsdp.extend_from_slice(b"RSD PTR ");
+        rsdp.push(0);
+        rsdp.extend_from_slice(b"BOCHS ");
+        rsdp.push(revision);
+        rsdp.extend_from_slice(&rsdt.to_le_bytes());
+        rsdp.extend_from_slice(&(Rsdp::SIZE as u32).to_le_bytes());
+        rsdp.extend_from_slice(&xsdt.to_le_bytes());
+        rsdp.extend_from_slice(&[0; 4]);
+        rsdp[8] = 0u8.wrapping_sub(checksum(&rsdp[..Rsdp::V1_SIZE]));
+        rsdp[32] = 0u8.wrapping_sub(checksum(&rsdp));
+        rsdp
+    }
+
+    #[test]
+    fn rsdp_of_acpi_1() {
+        let rsdp = Rsdp::parse(&rsdp(0, 0x7fe14ee, 0)).unwrap();
+        assert_eq!(rsdp.revision, 0);
+        assert_eq!(rsdp.oem_id(), "BOCHS");
+        assert_eq!(rsdp.xsdt_address, None);
+        assert_eq!(rsdp.root_table_address(), 0x7fe14ee);
+    }
+
+    #[test]
+    fn rsdp_prefers_the_xsdt() {
+        let rsdp = Rsdp::parse(&rsdp(2, 0x7fe14ee, 0x1_0000_0000)).unwrap();
+        assert_eq!(rsdp.xsdt_address, Some(0x1_0000_0000));
+        assert_eq!(rsdp.root_table_address(), 0x1_0000_0000);
+    }
+
+    #[test]
+    fn rsdp_checksums_are_checked() {
+        let mut bytes = rsdp(2, 0x1000, 0x2000);
+        bytes[16] ^= 1;
+        assert_eq!(Rsdp::parse(&bytes), Err(AcpiError::BadChecksum));
+        // the extended checksum covers the xsdt address
+        let mut bytes = rsdp(2, 0x1000, 0x2000);
+        bytes[24] ^= 1;
+        assert_eq!(Rsdp::parse(&bytes), Err(AcpiError::BadChecksum));
+        assert_eq!(Rsdp::parse(b"RSD PTX "), Err(AcpiError::Truncated));
+    }
+
+    #[test]
+    fn root_tables() {
+        let rsdt = build_table(b"RSDT", 1, &[0x00, 0x10, 0, 0, 0x00, 0x20, 0, 0]);
+        assert_eq!(parse_root_table(&rsdt), Ok(vec![0x1000, 0x2000]));
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&0x1_0000_1000u64.to_le_bytes());
+        let xsdt = build_table(b"XSDT", 1, &body);
+        assert_eq!(parse_root_table(&xsdt), Ok(vec![0x1_0000_1000]));
+    }
+
+    #[test]
+    fn tables_are_validated() {
+        let mut table = build_table(b"HPET", 1, &[1, 2, 3]);
+        let header = SdtHeader::parse(&table).unwrap();
+        assert_eq!(header.signature(), "HPET");
+        assert_eq!(header.oem_table_id(), "TESTTBL");
+        assert_eq!(header.length, 39);
+
+        // trailing bytes are not part of the table
+        table.push(0xaa);
+        assert_eq!(validate_table(&table, b"HPET").unwrap().len(), 39);
+        assert_eq!(
+            validate_table(&table, b"APIC"),
+            Err(AcpiError::BadSignature)
+        );
+        assert_eq!(
+            validate_table(&table[..38], b"HPET"),
+            Err(AcpiError::Truncated)
+        );
+        table[37] ^= 0xff;
+        assert_eq!(validate_table(&table, b"HPET"), Err(AcpiError::BadChecksum));
+    }
+}
@@ -0,0 +1,100 @@
+use core::ops::Range;
+
+pub trait BitRange {
+    fn bit(&self, i: usize) -> bool;
+    fn bit_range(&self, range: Range<usize>) -> Self;
+}
+
+impl BitRange for u64 {
+    fn bit(&self, i: usize) -> bool {
+        self & (1 << i) != 0
+    }
+
+    fn bit_range(&self, range: Range<usize>) -> u64 {
+        let s = 64;
+        let h = (self << (s - range.end)) >> (s - range.end);
+        h >> range.start
+    }
+}
+
+#[derive(Debug)]
+pub enum AlignmentError {
+    InvalidAlignment,
+    AlignmentNotPossible,
+}
+
+pub fn find_next_aligned_byte(ptr: *const u8, align: usize) -> Result<*mut u8, AlignmentError> {
+    if align == 0 || (align & (align - 1)) != 0 {
+        // Return an error if alignment is not a power of two or is zero
+        return Err(AlignmentError::InvalidAlignment);
+    }
+
+    let offset = ptr.align_offset(align);
+    if offset == usize::MAX {
+        // Return an error if alignment is not possible
+        return Err(AlignmentError::AlignmentNotPossible);
+    }
+
+    // Calculate the new aligned address and return it
+    Ok((ptr as usize + offset) as *mut u8)
+}
+
+#[macro_export]
+macro_rules! bit {
+    ($x:expr) => {
+        1 << $x
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn bit_range_of_known_value() {
+        let value: u64 = 0xdead_beef;
+        assert_eq!(value.bit_range(0..4), 0xf);
+        assert_eq!(value.bit_range(16..32), 0xdead);
+        assert_eq!(value.bit_range(0..64), value);
+        assert!(value.bit(0));
+        assert!(!value.bit(4));
+    }
+
+    #[test]
+    fn rejects_invalid_alignment() {
+        let ptr = 0x1000 as *const u8;
+        assert!(matches!(
+            find_next_aligned_byte(ptr, 0),
+            Err(AlignmentError::InvalidAlignment)
+        ));
+        assert!(matches!(
+            find_next_aligned_byte(ptr, 3),
+            Err(AlignmentError::InvalidAlignment)
+        ));
+    }
+
+    proptest! {
+        #[test]
+        fn bit_range_matches_shift_and_mask(value: u64, start in 0usize..64, len in 1usize..=64) {
+            let end = (start + len).min(64);
+            let mask = if end - start == 64 { u64::MAX } else { (1 << (end - start)) - 1 };
+            prop_assert_eq!(value.bit_range(start..end), (value >> start) & mask);
+        }
+
+        #[test]
+        fn bit_agrees_with_bit_range(value: u64, i in 0usize..64) {
+            prop_assert_eq!(value.bit(i), value.bit_range(i..i + 1) == 1);
+        }
+
+        #[test]
+        fn aligned_byte_is_next_multiple(addr in 1usize..usize::MAX / 2, shift in 0u32..20) {
+            let align = 1 << shift;
+            let aligned = find_next_aligned_byte(addr as *const u8, align).unwrap() as usize;
+            prop_assert_eq!(aligned % align, 0);
+            prop_assert!(aligned >= addr);
+            prop_assert!(aligned - addr < align);
+        }
+    }
+}
@@ -0,0 +1,216 @@
+trait ToUnicode {
+    fn to_unicode(&self) -> u16;
+}
+
+impl ToUnicode for [u8] {
+    fn to_unicode(&self) -> u16 {
+        if self[0] >> 5 == 0b110 {
+            (((self[0] & 0b0011_1111) as u16) << 6) + ((self[1] & 0b0011_1111) as u16)
+        } else if self[0] >> 4 == 0b1110 {
+            (((self[0] & 0b0000_1111) as u16) << 12)
+                + (((self[1] & 0b001_11111) as u16) << 6)
+                + ((self[2] & 0b0011_1111) as u16)
+        } else if self[0] >> 7 == 0b0 {
+            (self[0] & 0b0111_1111) as u16
+        } else {
+            // TODO implement
+            panic!("not a valid UTF-8 or i was too lazy to implement the missing option!");
+        }
+    }
+}
+
+pub type GlyphBitmap = [u8; 64];
+
+#[derive(Debug)]
+pub struct Glyph<'a> {
+    pub height_px: u32,
+    pub width_px: u32,
+    pub bitmap: &'a GlyphBitmap,
+}
+
+#[repr(C, packed)]
+pub struct BitmapTable<'a> {
+    map: &'a [GlyphBitmap],
+}
+
+impl<'a> BitmapTable<'a> {
+    /// ```glyphs``` starts where the glyph bitmaps in the psf file are stored (psf file start +
+    /// header size)
+    pub fn new(glyphs: &'a [u8], num_glyphs: usize) -> Self {
+        assert!(
+            glyphs.len() >= num_glyphs * core::mem::size_of::<GlyphBitmap>(),
+            "psf file is truncated"
+        );
+        Self {
+            // GlyphBitmap is a byte array, so there is no alignment to care about
+            map: unsafe {
+                core::slice::from_raw_parts(glyphs.as_ptr() as *const GlyphBitmap, num_glyphs)
+            },
+        }
+    }
+}
+
+pub struct UnicodeTable {
+    table: [u16; u16::MAX as usize],
+}
+
+impl UnicodeTable {
+    /// ```psf_unicode_table``` is the mapping table at the end of the psf file (psf file start +
+    /// headersize + glpyhsize * bytes per glypth)
+    pub fn new(psf_unicode_table: &[u8]) -> Self {
+        let mut unicode_table = Self {
+            table: [0u16; u16::MAX as usize],
+        };
+
+        for (i, codes) in psf_unicode_table.split(|byte| *byte == 0xFF).enumerate() {
+            let mut byte = 0;
+            while byte < codes.len() {
+                let unicode: u16 = if codes[byte] >> 5 == 0b110 {
+                    let unicode = codes[byte..byte + 2].to_unicode();
+                    byte += 2;
+                    unicode
+                } else if codes[byte] >> 4 == 0b1110 {
+                    let unicode = codes[byte..byte + 3].to_unicode();
+                    byte += 3;
+                    unicode
+                } else if codes[byte] >> 7 == 0b0 {
+                    let unicode = codes[byte..byte + 1].to_unicode();
+                    byte += 1;
+                    unicode
+                } else {
+                    // TODO implement
+                    panic!("not a valid UTF-8 or i was too lazy to implement the missing option!");
+                };
+                unicode_table.table[unicode as usize] = i as u16;
+            }
+        }
+        unicode_table
+    }
+}
+
+pub struct PSFFont<'a> {
+    // maps unicode to Bitmap. unicode is index into BitmapTable
+    bitmap_table: BitmapTable<'a>,
+    // maps unicode to index into `bitmap_table` for the glyph
+    unicode_table: UnicodeTable,
+    height_px: u32,
+    width_px: u32,
+}
+
+impl<'a> PSFFont<'a> {
+    pub fn new(
+        height_px: u32,
+        width_px: u32,
+        bitmap_table: BitmapTable<'a>,
+        unicode_table: UnicodeTable,
+    ) -> Self {
+        PSFFont {
+            height_px,
+            width_px,
+            bitmap_table,
+            unicode_table,
+        }
+    }
+
+    /// Parses a whole psf 2 file.
+    pub fn from_bytes(file: &'a [u8]) -> Self {
+        let psf_header = PsfHeader::new(file);
+        let glyphs = &file[psf_header.headersize as usize..];
+        let bitmap_table = BitmapTable::new(glyphs, psf_header.numglpyh as usize);
+        let unicode_table_start = psf_header.headersize as usize
+            + (psf_header.bytesperglyph * psf_header.numglpyh) as usize;
+        let unicode_table = UnicodeTable::new(&file[unicode_table_start..]);
+        PSFFont::new(
+            psf_header.height,
+            psf_header.width,
+            bitmap_table,
+            unicode_table,
+        )
+    }
+
+    pub fn get_glyph(&self, char: char) -> Glyph<'_> {
+        Glyph {
+            height_px: self.height_px,
+            width_px: self.width_px,
+            bitmap: &self.bitmap_table.map[self.unicode_table.table[char as usize] as usize],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct PsfHeader {
+    magic: u32,
+    version: u32,
+    pub headersize: u32,
+    flags: u32,
+    numglpyh: u32,
+    pub bytesperglyph: u32,
+    pub height: u32,
+    pub width: u32,
+}
+
+impl PsfHeader {
+    /// ```file``` starts with the psf header
+    pub fn new(file: &[u8]) -> Self {
+        assert!(
+            file.len() >= core::mem::size_of::<PsfHeader>(),
+            "psf file is truncated"
+        );
+        unsafe { (file.as_ptr() as *const PsfHeader).read_unaligned() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    const PSF2_MAGIC: u32 = 0x864ab572;
+    const FONT: &[u8] = include_bytes!("../../Uni3-TerminusBold32x16.psf");
+
+    #[test]
+    fn header_of_terminus() {
+        let header = PsfHeader::new(FONT);
+        let magic = header.magic;
+        let (height, width, bytes_per_glyph) = (header.height, header.width, header.bytesperglyph);
+        assert_eq!(magic, PSF2_MAGIC);
+        assert_eq!((height, width), (32, 16));
+        // one bit per pixel, every row padded to full bytes
+        assert_eq!(bytes_per_glyph, 32 * 2);
+    }
+
+    #[test]
+    fn utf8_to_unicode() {
+        assert_eq!(b"A".to_unicode(), 'A' as u16);
+        assert_eq!("é".as_bytes().to_unicode(), 'é' as u16);
+        assert_eq!("€".as_bytes().to_unicode(), '€' as u16);
+    }
+
+    #[test]
+    fn glyphs_are_looked_up_by_char() {
+        let font = PSFFont::from_bytes(FONT);
+        let space = font.get_glyph(' ');
+        let letter = font.get_glyph('A');
+        assert!(space.bitmap.iter().all(|&row| row == 0));
+        assert!(letter.bitmap.iter().any(|&row| row != 0));
+        assert_ne!(space.bitmap, letter.bitmap);
+    }
+
+    #[test]
+    #[should_panic]
+    fn truncated_header_is_rejected() {
+        PsfHeader::new(&FONT[..16]);
+    }
+
+    proptest! {
+        #[test]
+        fn to_unicode_decodes_the_basic_multilingual_plane(
+            c in proptest::char::range('\0', '\u{ffff}'),
+        ) {
+            let mut utf8 = [0; 4];
+            prop_assert_eq!(c.encode_utf8(&mut utf8).as_bytes().to_unicode(), c as u16);
+        }
+    }
+}
@@ -0,0 +1,15 @@
+// The parts of the kernel that don't touch any hardware: address and page table encoding, the
+// physical page bitmap, the PSF font parser, the ACPI table parsers and the clock arithmetic.
+// Nothing in here needs our target, so it builds for the host as well and is tested with a plain
+// `cargo test`.
+#![cfg_attr(not(test), no_std)]
+#![feature(allocator_api)]
+
+extern crate alloc;
+
+pub mod acpi;
+pub mod bit_utils;
+pub mod font;
+pub mod mem;
+pub mod paging;
+pub mod time;
@@ -0,0 +1,293 @@
+use alloc::vec::Vec;
+use core::alloc::Allocator;
+
+use crate::bit;
+use crate::mem::page::{calc_4kb_page_count, Page, PageSize};
+use crate::mem::{calc_mem_available, MemoryMapEntry, PageFrameAllocator};
+use crate::paging::PhysAddr;
+
+pub struct Bitmap<'a>(pub &'a mut [u8]);
+
+impl<'a> Bitmap<'a> {
+    pub fn new(bitmap: &'a mut [u8]) -> Self {
+        Bitmap(bitmap)
+    }
+
+    pub fn find_free_4kb_page(&self) -> Option<Page> {
+        for (i, &pagebyte) in self.0.iter().enumerate() {
+            if pagebyte != u8::MAX {
+                for bit in 0..8 {
+                    if bit!(bit) & pagebyte == 0 {
+                        let index = i * 8 + bit;
+                        return Some(pagekb4_from_index(index));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Marks every 4Kb page that overlaps `start..start + length` as used.
+    ///
+    /// Used to hide memory from the allocator that is usable according to the memory map but
+    /// already handed out, e.g. the bootstrap heap the bitmap itself lives in.
+    pub fn mark_range_used(&mut self, start: PhysAddr, length: u64) {
+        let kb4 = PageSize::KB4 as u64;
+        let first_page = start.as_u64() / kb4;
+        let end_page = (start.as_u64() + length).div_ceil(kb4);
+        for index in first_page..end_page {
+            self.set_bit(index as usize, true);
+        }
+    }
+
+    fn set_bit(&mut self, index: usize, used: bool) {
+        let Some(pagebyte) = self.0.get_mut(index / 8) else {
+            return;
+        };
+        if used {
+            *pagebyte |= bit!(index % 8);
+        } else {
+            *pagebyte &= !bit!(index % 8);
+        }
+    }
+}
+
+impl<'a> PageFrameAllocator for Bitmap<'a> {
+    fn allocate_frame(&mut self) -> Option<Page> {
+        let page = self.find_free_4kb_page()?;
+        self.set_bit((page.start.as_u64() / PageSize::KB4 as u64) as usize, true);
+        Some(page)
+    }
+
+    fn deallocate_frame(&mut self, page: Page) {
+        self.set_bit((page.start.as_u64() / PageSize::KB4 as u64) as usize, false);
+    }
+}
+
+pub fn create_bitmap<E: MemoryMapEntry, T: Allocator>(entries: &[&E], allocator: T) -> Vec<u8, T> {
+    let mem_available = calc_mem_available(entries);
+    // each byte represents 8 pages.
+    let bitmap_size = calc_4kb_page_count(mem_available) / 8;
+    let mut bitmap_vec = Vec::with_capacity_in(bitmap_size as usize, allocator);
+    bitmap_vec.resize(bitmap_size as usize, 0);
+
+    for (pagebyte_index, pagebyte) in bitmap_vec.iter_mut().enumerate() {
+        *pagebyte = set_used_page_bits(pagebyte_index, entries);
+    }
+    bitmap_vec
+}
+
+fn set_used_page_bits<E: MemoryMapEntry>(pagebyte_index: usize, entries: &[&E]) -> u8 {
+    let mut pagebyte = 0;
+    for bit in 0..8 {
+        let page = pagekb4_from_index(pagebyte_index * 8 + bit);
+        if !is_page_entirely_free(&page, entries) {
+            pagebyte |= bit!(bit);
+        }
+    }
+    pagebyte
+}
+
+fn pagekb4_from_index(index: usize) -> Page {
+    Page {
+        start: PhysAddr::new((index * PageSize::KB4 as usize) as u64),
+        size: PageSize::KB4,
+    }
+}
+
+/// A page is free if it lies entirely inside a single usable memory map entry.
+///
+/// Holes in the memory map (e.g. the legacy VGA area or the PCI hole) are not backed by RAM and
+/// therefore never free, the same goes for every page that only partially overlaps a usable entry.
+/// Some firmware reports reserved entries overlapping usable ones, the reserved entry wins.
+pub fn is_page_entirely_free<E: MemoryMapEntry>(page: &Page, entries: &[&E]) -> bool {
+    let page_start = page.start.as_u64();
+    let page_end = page_start + page.size as u64;
+    let inside_usable = entries.iter().any(|entry| {
+        entry.is_usable() && entry.base() <= page_start && entry.base() + entry.length() >= page_end
+    });
+    let overlaps_reserved = entries.iter().any(|entry| {
+        !entry.is_usable() && entry.base() < page_end && page_start < entry.base() + entry.length()
+    });
+    inside_usable && !overlaps_reserved
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    const KB4: u64 = PageSize::KB4 as u64;
+
+    struct Entry {
+        base: u64,
+        length: u64,
+        usable: bool,
+    }
+
+    impl MemoryMapEntry for Entry {
+        fn base(&self) -> u64 {
+            self.base
+        }
+
+        fn length(&self) -> u64 {
+            self.length
+        }
+
+        fn is_usable(&self) -> bool {
+            self.usable
+        }
+    }
+
+    fn entry(base: u64, length: u64, usable: bool) -> Entry {
+        Entry {
+            base,
+            length,
+            usable,
+        }
+    }
+
+    fn page(index: u64) -> Page {
+        Page::new(PhysAddr::new(index * KB4), PageSize::KB4)
+    }
+
+    #[test]
+    fn finds_first_free_page() {
+        let mut bits = [0b0000_0111, 0];
+        let bitmap = Bitmap::new(&mut bits);
+        assert_eq!(
+            bitmap.find_free_4kb_page().unwrap().start,
+            PhysAddr::new(3 * KB4)
+        );
+    }
+
+    #[test]
+    fn full_bitmap_has_no_free_page() {
+        let mut bits = [u8::MAX; 4];
+        let bitmap = Bitmap::new(&mut bits);
+        assert!(bitmap.find_free_4kb_page().is_none());
+    }
+
+    #[test]
+    fn mark_range_used_covers_partial_pages() {
+        let mut bits = [0; 2];
+        let mut bitmap = Bitmap::new(&mut bits);
+        // touches pages 1 to 3
+        bitmap.mark_range_used(PhysAddr::new(KB4 + 8), 2 * KB4);
+        assert_eq!(bitmap.0[0], 0b0000_1110);
+        assert_eq!(bitmap.find_free_4kb_page().unwrap().start, PhysAddr::new(0));
+    }
+
+    #[test]
+    fn mark_range_used_ignores_pages_past_the_end() {
+        let mut bits = [0; 1];
+        let mut bitmap = Bitmap::new(&mut bits);
+        bitmap.mark_range_used(PhysAddr::new(6 * KB4), 4 * KB4);
+        assert_eq!(bitmap.0[0], 0b1100_0000);
+    }
+
+    #[test]
+    fn allocate_and_deallocate_frames() {
+        let mut bits = [0; 1];
+        let mut bitmap = Bitmap::new(&mut bits);
+        let first = bitmap.allocate_frame().unwrap();
+        let second = bitmap.allocate_frame().unwrap();
+        assert_eq!(first.start, PhysAddr::new(0));
+        assert_eq!(second.start, PhysAddr::new(KB4));
+
+        bitmap.deallocate_frame(first);
+        assert_eq!(bitmap.allocate_frame().unwrap().start, PhysAddr::new(0));
+    }
+
+    #[test]
+    fn pages_in_holes_and_reserved_entries_are_not_free() {
+        // usable 0x0-0x3000, hole, reserved 0x4000-0x5000, usable 0x5000-0x8000
+        let entries = [
+            entry(0, 3 * KB4, true),
+            entry(4 * KB4, KB4, false),
+            entry(5 * KB4, 3 * KB4, true),
+        ];
+        let entries: Vec<&Entry> = entries.iter().collect();
+        let free: Vec<bool> = (0..9)
+            .map(|i| is_page_entirely_free(&page(i), &entries))
+            .collect();
+        assert_eq!(
+            free,
+            [true, true, true, false, false, true, true, true, false]
+        );
+    }
+
+    #[test]
+    fn partially_covered_page_is_not_free() {
+        let entries = [entry(KB4 / 2, 2 * KB4, true)];
+        let entries: Vec<&Entry> = entries.iter().collect();
+        assert!(!is_page_entirely_free(&page(0), &entries));
+        assert!(is_page_entirely_free(&page(1), &entries));
+        assert!(!is_page_entirely_free(&page(2), &entries));
+    }
+
+    #[test]
+    fn create_bitmap_marks_everything_but_usable_pages() {
+        let entries = [
+            entry(0, 2 * KB4, false),
+            entry(2 * KB4, 12 * KB4, true),
+            entry(14 * KB4, 2 * KB4, false),
+        ];
+        let entries: Vec<&Entry> = entries.iter().collect();
+        let bits = create_bitmap(&entries, alloc::alloc::Global);
+        assert_eq!(bits.as_slice(), [0b0000_0011, 0b1100_0000]);
+    }
+
+    proptest! {
+        #[test]
+        fn finds_lowest_clear_bit(bits in proptest::collection::vec(any::<u8>(), 1..32)) {
+            let mut copy = bits.clone();
+            let bitmap = Bitmap::new(&mut copy);
+            let expected = (0..bits.len() * 8).find(|&i| bits[i / 8] & (1 << (i % 8)) == 0);
+            let found = bitmap
+                .find_free_4kb_page()
+                .map(|page| (page.start.as_u64() / KB4) as usize);
+            prop_assert_eq!(found, expected);
+        }
+
+        #[test]
+        fn mark_range_used_sets_exactly_the_touched_pages(
+            start in 0u64..64 * KB4,
+            length in 1u64..64 * KB4,
+        ) {
+            let mut bits = [0u8; 32];
+            let mut bitmap = Bitmap::new(&mut bits);
+            bitmap.mark_range_used(PhysAddr::new(start), length);
+            for index in 0..256u64 {
+                let touched = index * KB4 < start + length && (index + 1) * KB4 > start;
+                prop_assert_eq!(bits[index as usize / 8] & (1 << (index % 8)) != 0, touched);
+            }
+        }
+
+        #[test]
+        fn allocated_frames_are_distinct(count in 0usize..64) {
+            let mut bits = [0u8; 8];
+            let mut bitmap = Bitmap::new(&mut bits);
+            let mut frames: Vec<u64> = (0..count)
+                .map(|_| bitmap.allocate_frame().unwrap().start.as_u64())
+                .collect();
+            frames.dedup();
+            prop_assert_eq!(frames.len(), count);
+        }
+
+        #[test]
+        fn free_pages_lie_inside_a_usable_entry(
+            base in 0u64..64,
+            length in 0u64..64,
+            index in 0u64..160,
+        ) {
+            let entries = [entry(base * KB4 / 2, length * KB4 / 2, true)];
+            let entries: Vec<&Entry> = entries.iter().collect();
+            let page = page(index);
+            let inside =
+                base * KB4 / 2 <= index * KB4 && (index + 1) * KB4 <= (base + length) * KB4 / 2;
+            prop_assert_eq!(is_page_entirely_free(&page, &entries), inside);
+        }
+    }
+}
@@ -1,14 +1,19 @@
-use core::alloc::{AllocError, GlobalAlloc, Layout};
+use core::alloc::{AllocError, Layout};
 use core::ptr::NonNull;
 
-use limine::memory_map;
-use limine::response::{HhdmResponse, MemoryMapResponse};
-
 use crate::bit_utils::AlignmentError;
-
-pub fn init_bootstrap_alloc(
-    mm: &MemoryMapResponse,
-    hhdm_offset_response: &HhdmResponse,
+use crate::mem::MemoryMapEntry;
+
+/// Size of the bootstrap heap, [`init_bootstrap_alloc`] explains the number.
+pub const BOOTSTRAP_HEAP_SIZE: usize = 1 << 26;
+
+/// Sets up the bootstrap heap in the first usable block of the memory map that fits it.
+///
+/// `hhdm_offset` is the offset of the direct map the heap is accessed through. Outside of the
+/// kernel this can point anywhere, e.g. into a host buffer standing in for physical memory.
+pub fn init_bootstrap_alloc<E: MemoryMapEntry>(
+    entries: &[&E],
+    hhdm_offset: u64,
 ) -> BootstrapAllocator {
     // the goal is to have a variable sized bitmap that tracks the free physical pages, that the sys
     // allocator can reference. since we do not know the size of the bitmap at compile time, we
@@ -32,27 +37,37 @@ pub fn init_bootstrap_alloc(
     // since it is a direct map we know that free phsyical pages within the 4Gb direct map
     // respond to free addresses in the virtual address space.
 
-    const BOOTSTRAP_HEAP_SIZE: usize = 1 << 26;
-
-    let start_ptr = find_memblock(BOOTSTRAP_HEAP_SIZE, mm.entries(), 1)
+    let start = find_memblock(BOOTSTRAP_HEAP_SIZE, entries, 1)
         .unwrap_or_else(|| panic!("couldn't find space for bootstrap heap!"));
-    let hhdm_start_ptr = unsafe { start_ptr.offset(hhdm_offset_response.offset() as isize) };
+    // the offset is only "negative" when the direct map is faked on the host
+    let hhdm_start_ptr = start.wrapping_add(hhdm_offset) as *mut u8;
 
     BootstrapAllocator::new(hhdm_start_ptr, BOOTSTRAP_HEAP_SIZE)
 }
 
-fn find_memblock(size: usize, mm_entries: &[&memory_map::Entry], align: usize) -> Option<*mut u8> {
-    for entry in mm_entries.iter() {
-        let entry_base = entry.base as *const u8;
-
-        let align_offset = entry_base.align_offset(align);
-
-        let kb4_aligned_entry_base = unsafe { entry_base.add(align_offset) };
-        let diff = align_offset;
-        let space = entry.length - diff as u64;
-
-        if space >= size as u64 {
-            return Some(kb4_aligned_entry_base as *mut u8);
+/// Physical address of the first block of `size` bytes aligned to `align`, that lies inside a
+/// usable entry and overlaps no other entry.
+///
+/// Firmware does report reserved memory that overlaps usable entries, the reserved entry wins in
+/// that case.
+pub fn find_memblock<E: MemoryMapEntry>(size: usize, entries: &[&E], align: usize) -> Option<u64> {
+    let size = size as u64;
+    let align = align as u64;
+    for entry in entries.iter().filter(|entry| entry.is_usable()) {
+        let entry_end = entry.base() + entry.length();
+        let mut start = entry.base().next_multiple_of(align);
+        while start + size <= entry_end {
+            // restart behind the reserved memory in our way
+            match entries
+                .iter()
+                .filter(|other| !other.is_usable())
+                .find(|other| other.base() < start + size && start < other.base() + other.length())
+            {
+                Some(reserved) => {
+                    start = (reserved.base() + reserved.length()).next_multiple_of(align)
+                }
+                None => return Some(start),
+            }
         }
     }
     None
@@ -69,6 +84,15 @@ impl BootstrapAllocator {
     pub fn new(start: *mut u8, size: usize) -> Self {
         BootstrapAllocator { start, size }
     }
+
+    /// Virtual start address of the bootstrap heap inside the hhdm.
+    pub fn start(&self) -> *mut u8 {
+        self.start
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
 }
 
 unsafe impl core::alloc::Allocator for BootstrapAllocator {
@@ -84,17 +108,17 @@ unsafe impl core::alloc::Allocator for BootstrapAllocator {
 
         unsafe {
             // checking if the there is enough space to hold the bitmap
-            let highest_req_byte_addr = next_aligned_byte.offset(min_req_bytes as isize);
-            let highest_avail_byte_addr = self.start.offset(self.size as isize);
+            let highest_req_byte_addr = next_aligned_byte.add(min_req_bytes);
+            let highest_avail_byte_addr = self.start.add(self.size);
             if highest_avail_byte_addr.le(&highest_req_byte_addr) {
                 return Err(AllocError);
             }
             let ptr = core::ptr::slice_from_raw_parts_mut(next_aligned_byte, min_req_bytes);
-            return Ok(NonNull::new(ptr).unwrap());
+            Ok(NonNull::new(ptr).unwrap())
         }
     }
 
-    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+    unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {
         // im leaving this empty since we can just overwrite it
     }
 }
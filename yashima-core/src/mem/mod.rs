@@ -0,0 +1,49 @@
+use crate::mem::page::Page;
+
+pub mod bitmap;
+pub mod bootstrap_allocator;
+pub mod page;
+
+/// An entry of the memory map the bootloader hands us.
+///
+/// Keeps the memory code independent of limine, so it can be fed synthetic memory maps.
+pub trait MemoryMapEntry {
+    fn base(&self) -> u64;
+
+    fn length(&self) -> u64;
+
+    /// Whether the entry is RAM we are free to use.
+    fn is_usable(&self) -> bool;
+}
+
+#[cfg(feature = "limine")]
+impl MemoryMapEntry for limine::memory_map::Entry {
+    fn base(&self) -> u64 {
+        self.base
+    }
+
+    fn length(&self) -> u64 {
+        self.length
+    }
+
+    fn is_usable(&self) -> bool {
+        self.entry_type == limine::memory_map::EntryType::USABLE
+    }
+}
+
+pub trait PageFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<Page>;
+
+    fn deallocate_frame(&mut self, page: Page);
+}
+
+/// End of the highest entry of the memory map, the bitmap has to cover everything below it.
+pub fn calc_mem_available<E: MemoryMapEntry>(entries: &[&E]) -> u64 {
+    // the highest entry isn't necessarily the one with the highest base, reserved entries can
+    // overlap the ones below them
+    entries
+        .iter()
+        .map(|entry| entry.base() + entry.length())
+        .max()
+        .unwrap_or(0)
+}
@@ -0,0 +1,61 @@
+use crate::paging::PhysAddr;
+
+#[derive(Copy, Clone, Debug)]
+pub enum PageSize {
+    KB4 = 1 << 12,
+    MB2 = 1 << 21,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Page {
+    pub start: PhysAddr,
+    pub size: PageSize,
+}
+
+impl Page {
+    pub fn new(start: PhysAddr, size: PageSize) -> Self {
+        Page { start, size }
+    }
+}
+
+pub fn calc_4kb_page_count(mem_available: u64) -> u64 {
+    let kb4 = PageSize::KB4 as u64;
+
+    // overshoot is the amount of memory at the very "top" of the memory doesnt doesnt fill an
+    // entire 4kb page
+    let overshoot = mem_available % kb4;
+    let highest_aligned_addr = if overshoot == 0 {
+        mem_available
+    } else {
+        // round down to last full page
+        mem_available - overshoot
+    };
+
+    highest_aligned_addr / kb4
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    const KB4: u64 = PageSize::KB4 as u64;
+
+    #[test]
+    fn partial_pages_are_not_counted() {
+        assert_eq!(calc_4kb_page_count(0), 0);
+        assert_eq!(calc_4kb_page_count(KB4 - 1), 0);
+        assert_eq!(calc_4kb_page_count(KB4), 1);
+        assert_eq!(calc_4kb_page_count(3 * KB4 + 17), 3);
+    }
+
+    proptest! {
+        #[test]
+        fn page_count_covers_only_full_pages(mem_available: u64) {
+            let count = calc_4kb_page_count(mem_available);
+            prop_assert!(count * KB4 <= mem_available);
+            prop_assert!(mem_available - count * KB4 < KB4);
+        }
+    }
+}
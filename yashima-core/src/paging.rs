@@ -1,3 +1,7 @@
+use core::fmt;
+use core::ops::{Add, Sub};
+use core::sync::atomic::{AtomicU64, Ordering};
+
 use bitflags::bitflags;
 
 use crate::bit;
@@ -5,15 +9,42 @@ use crate::bit_utils::BitRange;
 use crate::mem::page::PageSize;
 
 const KB4: usize = PageSize::KB4 as usize;
-const PAGE_MASK_4KB: u64 = (usize::MAX & !KB4) as u64;
+// bits 51:12 of an entry hold the 4Kb aligned physical address
+const PAGE_MASK_4KB: u64 = (1 << 52) - KB4 as u64;
+
+/// Upper bound for the size of the hhdm.
+///
+/// Limine places the hhdm at the start of the higher half. It covers all of physical memory, which
+/// stays well below 64Tb, and everything we map ourselves (heap, mmio window, kernel image) lies
+/// further up.
+const HHDM_MAX_SIZE: u64 = 1 << 46;
+
+static HHDM_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// Sets the offset of the higher half direct map. Has to happen before the first conversion
+/// between physical and hhdm addresses.
+pub fn set_hhdm_offset(offset: u64) {
+    HHDM_OFFSET.store(offset, Ordering::Relaxed);
+}
+
+/// Offset of the higher half direct map limine sets up for us.
+pub fn hhdm_offset() -> u64 {
+    HHDM_OFFSET.load(Ordering::Relaxed)
+}
 
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(transparent)]
 pub struct PhysAddr(pub u64);
 
 impl PhysAddr {
-    pub fn new(addr: u64) -> Self {
+    pub const fn new(addr: u64) -> Self {
         Self(addr)
     }
 
+    pub const fn as_u64(self) -> u64 {
+        self.0
+    }
+
     pub fn raw_mut<T>(&self) -> *mut T {
         self.0 as *mut T
     }
@@ -21,6 +52,224 @@ impl PhysAddr {
     pub fn raw<T>(&self) -> *const T {
         self.0 as *const T
     }
+
+    /// Rounds down to the next multiple of `align`, which has to be a power of two.
+    pub const fn align_down(self, align: u64) -> Self {
+        Self(align_down(self.0, align))
+    }
+
+    /// Rounds up to the next multiple of `align`, which has to be a power of two.
+    pub const fn align_up(self, align: u64) -> Self {
+        Self(align_up(self.0, align))
+    }
+
+    pub const fn is_aligned(self, align: u64) -> bool {
+        align_down(self.0, align) == self.0
+    }
+
+    /// The address this physical address is mapped to inside the hhdm.
+    pub fn to_virt(self) -> VirtAddr {
+        VirtAddr::from_phys(self)
+    }
+
+    /// Reinterprets the memory at this physical address as `T` by going through the hhdm.
+    ///
+    /// # Safety
+    ///
+    /// The memory has to hold a valid `T` for the returned lifetime and must not be mutated
+    /// while the reference is alive.
+    pub unsafe fn as_hhdm_ref<T>(self) -> &'static T {
+        &*self.to_virt().as_ptr::<T>()
+    }
+
+    /// Mutable version of [`PhysAddr::as_hhdm_ref`].
+    ///
+    /// # Safety
+    ///
+    /// Same as [`PhysAddr::as_hhdm_ref`], additionally no other reference to the memory may be
+    /// alive.
+    pub unsafe fn as_hhdm_mut<T>(self) -> &'static mut T {
+        &mut *self.to_virt().as_mut_ptr::<T>()
+    }
+}
+
+impl fmt::Debug for PhysAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PhysAddr({:#x})", self.0)
+    }
+}
+
+impl Add<u64> for PhysAddr {
+    type Output = PhysAddr;
+
+    fn add(self, rhs: u64) -> PhysAddr {
+        PhysAddr(self.0 + rhs)
+    }
+}
+
+impl Sub<PhysAddr> for PhysAddr {
+    type Output = u64;
+
+    fn sub(self, rhs: PhysAddr) -> u64 {
+        self.0 - rhs.0
+    }
+}
+
+/// Error returned when constructing a [`VirtAddr`] from a non canonical address.
+#[derive(Debug, Clone, Copy)]
+pub struct NotCanonical(pub u64);
+
+/// A canonical 48 bit virtual address.
+///
+/// With 4-level paging bits 63:48 have to be copies of bit 47, otherwise any access raises a #GP.
+/// Every `VirtAddr` is guaranteed to be canonical.
+///
+/// For the layout of the page table indices refer to [5.3.3 4-Kbyte Page Translation](https://www.amd.com/content/dam/amd/en/documents/processor-tech-docs/programmer-references/24593.pdf#page=205).
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct VirtAddr(u64);
+
+impl VirtAddr {
+    /// Panics if `addr` is not canonical. Usable in constants, where the check happens at compile
+    /// time.
+    pub const fn new(addr: u64) -> Self {
+        match Self::try_new(addr) {
+            Ok(addr) => addr,
+            Err(_) => panic!("virtual address is not canonical"),
+        }
+    }
+
+    pub const fn try_new(addr: u64) -> Result<Self, NotCanonical> {
+        let sign_extension = addr >> 47;
+        if sign_extension == 0 || sign_extension == 0x1ffff {
+            Ok(Self(addr))
+        } else {
+            Err(NotCanonical(addr))
+        }
+    }
+
+    /// Makes `addr` canonical by sign extending bit 47.
+    pub const fn new_truncate(addr: u64) -> Self {
+        Self((((addr << 16) as i64) >> 16) as u64)
+    }
+
+    pub fn from_ptr<T>(ptr: *const T) -> Self {
+        Self::new(ptr as u64)
+    }
+
+    /// The address `phys` is mapped to inside the hhdm.
+    pub fn from_phys(phys: PhysAddr) -> Self {
+        assert!(phys.0 < HHDM_MAX_SIZE, "{phys:?} is outside of the hhdm");
+        Self::new(phys.0 + hhdm_offset())
+    }
+
+    /// The physical address behind this address, if it lies inside the hhdm.
+    ///
+    /// This is plain arithmetic, no page walk is done. The kernel's `mapper::translate` handles
+    /// addresses outside of the hhdm.
+    pub fn to_phys(self) -> Option<PhysAddr> {
+        let offset = self.0.checked_sub(hhdm_offset())?;
+        if offset < HHDM_MAX_SIZE {
+            Some(PhysAddr(offset))
+        } else {
+            None
+        }
+    }
+
+    pub const fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    pub fn as_ptr<T>(self) -> *const T {
+        self.0 as *const T
+    }
+
+    pub fn as_mut_ptr<T>(self) -> *mut T {
+        self.0 as *mut T
+    }
+
+    /// Rounds down to the next multiple of `align`, which has to be a power of two.
+    pub const fn align_down(self, align: u64) -> Self {
+        // clearing low bits never touches the sign extension
+        Self(align_down(self.0, align))
+    }
+
+    /// Rounds up to the next multiple of `align`, which has to be a power of two. Panics if the
+    /// result is not canonical.
+    pub const fn align_up(self, align: u64) -> Self {
+        Self::new(align_up(self.0, align))
+    }
+
+    pub const fn is_aligned(self, align: u64) -> bool {
+        align_down(self.0, align) == self.0
+    }
+
+    /// Offset into the 4Kb page, bits 11:0.
+    pub const fn page_offset(self) -> u64 {
+        self.0 & 0xfff
+    }
+
+    /// Index into the PML4 table, bits 47:39.
+    pub const fn pml4_index(self) -> usize {
+        ((self.0 >> 39) & 0x1ff) as usize
+    }
+
+    /// Index into the page directory pointer table, bits 38:30.
+    pub const fn pdp_index(self) -> usize {
+        ((self.0 >> 30) & 0x1ff) as usize
+    }
+
+    /// Index into the page directory, bits 29:21.
+    pub const fn pd_index(self) -> usize {
+        ((self.0 >> 21) & 0x1ff) as usize
+    }
+
+    /// Index into the page table, bits 20:12.
+    pub const fn pt_index(self) -> usize {
+        ((self.0 >> 12) & 0x1ff) as usize
+    }
+}
+
+impl fmt::Debug for VirtAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "VirtAddr({:#x})", self.0)
+    }
+}
+
+impl Add<u64> for VirtAddr {
+    type Output = VirtAddr;
+
+    /// Panics if the result is not canonical.
+    fn add(self, rhs: u64) -> VirtAddr {
+        VirtAddr::new(self.0 + rhs)
+    }
+}
+
+impl Sub<u64> for VirtAddr {
+    type Output = VirtAddr;
+
+    /// Panics if the result is not canonical.
+    fn sub(self, rhs: u64) -> VirtAddr {
+        VirtAddr::new(self.0 - rhs)
+    }
+}
+
+impl Sub<VirtAddr> for VirtAddr {
+    type Output = u64;
+
+    fn sub(self, rhs: VirtAddr) -> u64 {
+        self.0 - rhs.0
+    }
+}
+
+const fn align_down(addr: u64, align: u64) -> u64 {
+    assert!(align.is_power_of_two(), "alignment has to be a power of two");
+    addr & !(align - 1)
+}
+
+const fn align_up(addr: u64, align: u64) -> u64 {
+    assert!(align.is_power_of_two(), "alignment has to be a power of two");
+    (addr + align - 1) & !(align - 1)
 }
 
 const NUM_PML4_ENTRIES: usize = 512;
@@ -63,6 +312,7 @@ impl PML4Entry {
 }
 
 bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct PML4Flags: u64 {
         // Present
         // This bit indicates whether the page-translation table or physical page is loaded
@@ -125,7 +375,7 @@ pub struct PDPEntry(pub u64);
 
 impl PDPEntry {
     pub fn new(phys_addr: PhysAddr, flags: PDPFlags) -> Self {
-        PDPEntry((phys_addr.0 & PAGE_MASK_4KB) | 0 << 7 | flags.bits())
+        PDPEntry((phys_addr.0 & PAGE_MASK_4KB) | flags.bits())
     }
 
     pub fn get_flags(&self) -> Option<PDPFlags> {
@@ -147,6 +397,7 @@ impl PDPEntry {
 }
 
 bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct PDPFlags: u64 {
         // Present
         // This bit indicates whether the page-translation table or physical page is loaded
@@ -233,6 +484,7 @@ impl PDEntry {
 }
 
 bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct PDFlags: u64 {
         // Present
         // This bit indicates whether the page-translation table or physical page is loaded
@@ -321,7 +573,7 @@ impl PTEntry {
     }
 
     pub fn get_flags(&self) -> Option<PTFlags> {
-        let flags = self.0.bit_range(0..9) | (self.0 & bit!(63));
+        let flags = self.0.bit_range(0..10) | (self.0 & bit!(63));
 
         PTFlags::from_bits(flags)
     }
@@ -340,6 +592,7 @@ impl PTEntry {
 }
 
 bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct PTFlags: u64 {
         // Present
         // This bit indicates whether the page-translation table or physical page is loaded
@@ -393,6 +646,11 @@ bitflags! {
         // switch. Use of the G bit requires the page-global enable bit in CR4 to be set to 1 (CR4.PGE=1). See
         // “Global Pages” on page 158 for more information on the global-page mechanism.
         const G = bit!(8);
+        // Copy On Write
+        // Software defined. Bits 11:9 are available to software and ignored by the processor. We use
+        // bit 9 to mark a page that is shared read-only and gets copied on the first write, see
+        // `mem::cow`.
+        const COW = bit!(9);
         // No Execute
         // When the NX bit
         // is cleared to 0, code can be executed from the mapped physical pages. When the NX bit is set to 1,
@@ -402,3 +660,131 @@ bitflags! {
         const NX = bit!(63);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn pt_entry_keeps_frame_address() {
+        // bit 12 is the lowest bit of the frame number and must survive the round trip
+        let frame = PhysAddr::new(0x1234_5000);
+        let entry = PTEntry::new(frame, PTFlags::P | PTFlags::RW | PTFlags::NX);
+        assert_eq!(entry.get_phys_addr(), frame);
+        assert_eq!(
+            entry.get_flags(),
+            Some(PTFlags::P | PTFlags::RW | PTFlags::NX)
+        );
+        assert!(entry.is_present());
+    }
+
+    #[test]
+    fn pt_entry_keeps_cow_bit() {
+        let entry = PTEntry::new(PhysAddr::new(0x1000), PTFlags::P | PTFlags::COW);
+        assert!(entry.get_flags().unwrap().contains(PTFlags::COW));
+        assert_eq!(entry.get_phys_addr(), PhysAddr::new(0x1000));
+    }
+
+    #[test]
+    fn pd_entry_detects_large_page() {
+        let entry = PDEntry::new(PhysAddr::new(0x20_0000), PDFlags::P | PDFlags::PS);
+        assert!(entry.maps_large_page());
+        let entry = PDEntry::new(PhysAddr::new(0x20_0000), PDFlags::P);
+        assert!(!entry.maps_large_page());
+    }
+
+    #[test]
+    fn virt_addr_table_indices() {
+        let addr = VirtAddr::new(0xffff_8000_4020_3123);
+        assert_eq!(addr.pml4_index(), 256);
+        assert_eq!(addr.pdp_index(), 1);
+        assert_eq!(addr.pd_index(), 1);
+        assert_eq!(addr.pt_index(), 3);
+        assert_eq!(addr.page_offset(), 0x123);
+    }
+
+    #[test]
+    fn virt_addr_canonical() {
+        assert!(VirtAddr::try_new(0x0000_7fff_ffff_ffff).is_ok());
+        assert!(VirtAddr::try_new(0xffff_8000_0000_0000).is_ok());
+        assert!(VirtAddr::try_new(0x0000_8000_0000_0000).is_err());
+        assert_eq!(
+            VirtAddr::new_truncate(0x0000_8000_0000_0000).as_u64(),
+            0xffff_8000_0000_0000
+        );
+    }
+
+    #[test]
+    fn virt_addr_alignment() {
+        let addr = VirtAddr::new(0x1234);
+        assert_eq!(addr.align_down(0x1000).as_u64(), 0x1000);
+        assert_eq!(addr.align_up(0x1000).as_u64(), 0x2000);
+        assert!(!addr.is_aligned(0x1000));
+        assert!(addr.align_up(0x1000).is_aligned(0x1000));
+    }
+
+    #[test]
+    #[should_panic]
+    fn virt_addr_new_rejects_non_canonical() {
+        VirtAddr::new(0x0000_8000_0000_0000);
+    }
+
+    fn pt_flags() -> impl Strategy<Value = PTFlags> {
+        any::<u64>().prop_map(PTFlags::from_bits_truncate)
+    }
+
+    proptest! {
+        #[test]
+        fn pt_entry_round_trip(frame in 0u64..1 << 40, flags in pt_flags()) {
+            let frame = PhysAddr::new(frame << 12);
+            let entry = PTEntry::new(frame, flags);
+            prop_assert_eq!(entry.get_phys_addr(), frame);
+            prop_assert_eq!(entry.get_flags(), Some(flags));
+        }
+
+        #[test]
+        fn pt_entry_drops_offset_bits(addr in 0u64..1 << 52) {
+            let entry = PTEntry::new(PhysAddr::new(addr), PTFlags::P);
+            prop_assert_eq!(entry.get_phys_addr(), PhysAddr::new(addr).align_down(KB4 as u64));
+        }
+
+        #[test]
+        fn pd_entry_round_trip(frame in 0u64..1 << 31, large: bool) {
+            let frame = PhysAddr::new(frame << 21);
+            let flags = if large { PDFlags::P | PDFlags::PS } else { PDFlags::P | PDFlags::RW };
+            let entry = PDEntry::new(frame, flags);
+            prop_assert_eq!(entry.get_phys_addr(), frame);
+            prop_assert_eq!(entry.maps_large_page(), large);
+        }
+
+        #[test]
+        fn truncated_addresses_are_canonical(addr: u64) {
+            let truncated = VirtAddr::new_truncate(addr);
+            prop_assert!(VirtAddr::try_new(truncated.as_u64()).is_ok());
+            prop_assert_eq!(truncated.as_u64() & ((1 << 48) - 1), addr & ((1 << 48) - 1));
+        }
+
+        #[test]
+        fn indices_rebuild_the_address(addr: u64) {
+            let addr = VirtAddr::new_truncate(addr);
+            let rebuilt = (addr.pml4_index() as u64) << 39
+                | (addr.pdp_index() as u64) << 30
+                | (addr.pd_index() as u64) << 21
+                | (addr.pt_index() as u64) << 12
+                | addr.page_offset();
+            prop_assert_eq!(VirtAddr::new_truncate(rebuilt), addr);
+        }
+
+        #[test]
+        fn alignment_brackets_the_address(addr in 0u64..1 << 46, shift in 0u32..30) {
+            let align = 1 << shift;
+            let addr = PhysAddr::new(addr);
+            let (down, up) = (addr.align_down(align), addr.align_up(align));
+            prop_assert!(down.is_aligned(align) && up.is_aligned(align));
+            prop_assert!(down <= addr && addr <= up);
+            prop_assert!(up - down == 0 || up - down == align);
+        }
+    }
+}
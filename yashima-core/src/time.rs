@@ -0,0 +1,317 @@
+// Turning counter readings into time and RTC readings into dates. The kernel reads the counters
+// (TSC, HPET, PIT) and the RTC, the arithmetic on what they return lives here.
+
+use core::fmt;
+
+use crate::bit;
+
+pub const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Nanoseconds that `cycles` ticks of a counter running at `frequency` Hz take.
+pub fn cycles_to_ns(cycles: u64, frequency: u64) -> u64 {
+    (cycles as u128 * NANOS_PER_SEC as u128 / frequency as u128) as u64
+}
+
+/// A monotonic nanosecond clock driven by a counter that can be narrower than 64 bits.
+///
+/// Every reading of the counter goes through [`Monotonic::update`]. A counter that wraps has to
+/// be read at least once per wrap, otherwise whole wraps go missing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Monotonic {
+    frequency: u64,
+    /// The implemented bits of the counter, it wraps after `mask + 1` ticks.
+    mask: u64,
+    /// The last reading.
+    last: u64,
+    /// Ticks since the counter took over.
+    cycles: u64,
+    /// The time when the counter took over.
+    base_ns: u64,
+}
+
+impl Monotonic {
+    /// A clock at 0 whose counter currently reads `now`.
+    pub fn new(frequency: u64, mask: u64, now: u64) -> Self {
+        assert!(frequency > 0, "a counter without a frequency");
+        Monotonic {
+            frequency,
+            mask,
+            last: now & mask,
+            cycles: 0,
+            base_ns: 0,
+        }
+    }
+
+    /// Advances the clock to the counter reading `now` and returns the time in nanoseconds.
+    pub fn update(&mut self, now: u64) -> u64 {
+        let now = now & self.mask;
+        self.cycles += now.wrapping_sub(self.last) & self.mask;
+        self.last = now;
+        self.ns()
+    }
+
+    /// The time of the last update.
+    pub fn ns(&self) -> u64 {
+        self.base_ns + cycles_to_ns(self.cycles, self.frequency)
+    }
+
+    /// Continues from the time of the last update with another counter, which reads `now`.
+    pub fn switch(&mut self, frequency: u64, mask: u64, now: u64) {
+        let base_ns = self.ns();
+        *self = Monotonic::new(frequency, mask, now);
+        self.base_ns = base_ns;
+    }
+}
+
+/// A UTC date and time, as the RTC keeps it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1-12.
+    pub month: u8,
+    /// 1-31.
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// The date `secs` seconds after 1970-01-01 00:00:00.
+    pub fn from_unix(secs: u64) -> DateTime {
+        let (year, month, day) = civil_from_days((secs / SECS_PER_DAY) as i64);
+        let secs = secs % SECS_PER_DAY;
+        DateTime {
+            year: year as u16,
+            month,
+            day,
+            hour: (secs / 3600) as u8,
+            minute: (secs / 60 % 60) as u8,
+            second: (secs % 60) as u8,
+        }
+    }
+
+    /// Seconds since 1970-01-01 00:00:00, dates before are clamped to 0.
+    pub fn unix_timestamp(&self) -> u64 {
+        let days = days_from_civil(self.year as i64, self.month, self.day);
+        if days < 0 {
+            return 0;
+        }
+        let secs = self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64;
+        days as u64 * SECS_PER_DAY + secs
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+const SECS_PER_DAY: u64 = 24 * 3600;
+
+// both conversions count in eras of 400 years, which start on march 1st so the leap day is the
+// last day of the year. See http://howardhinnant.github.io/date_algorithms.html
+const DAYS_PER_ERA: i64 = 146_097;
+/// Days from 0000-03-01 to 1970-01-01.
+const UNIX_EPOCH_DAYS: i64 = 719_468;
+
+/// Days since 1970-01-01 of a date in the proleptic gregorian calendar.
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_from_march = (month as i64 + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * DAYS_PER_ERA + day_of_era - UNIX_EPOCH_DAYS
+}
+
+/// The date `days` days after 1970-01-01 as year, month and day.
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let days = days + UNIX_EPOCH_DAYS;
+    let era = days.div_euclid(DAYS_PER_ERA);
+    let day_of_era = days - era * DAYS_PER_ERA;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u8;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    } as u8;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// The time registers of the CMOS RTC as they were read, still in the format status register B
+/// selects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RtcRegisters {
+    pub second: u8,
+    pub minute: u8,
+    /// In 12 hour mode bit 7 is set for PM.
+    pub hour: u8,
+    pub day: u8,
+    pub month: u8,
+    /// The year within the century.
+    pub year: u8,
+    /// Not every RTC has a century register, the FADT tells where it is.
+    pub century: Option<u8>,
+    pub status_b: u8,
+}
+
+impl RtcRegisters {
+    /// Set in status register B if the values are binary instead of BCD.
+    pub const BINARY: u8 = bit!(2);
+    /// Set in status register B if the hours count 0-23 instead of 1-12.
+    pub const HOUR_24: u8 = bit!(1);
+    const PM: u8 = bit!(7);
+
+    /// Decodes the registers. Without a century register the year is taken to be in the 2000s.
+    pub fn decode(&self) -> DateTime {
+        let binary = self.status_b & Self::BINARY != 0;
+        let value = |raw: u8| if binary { raw } else { bcd_to_binary(raw) };
+
+        let mut hour = value(self.hour & !Self::PM);
+        if self.status_b & Self::HOUR_24 == 0 {
+            // 12 AM is midnight and 12 PM noon
+            hour %= 12;
+            if self.hour & Self::PM != 0 {
+                hour += 12;
+            }
+        }
+        let century = self.century.map_or(20, value) as u16;
+        DateTime {
+            year: century * 100 + value(self.year) as u16,
+            month: value(self.month),
+            day: value(self.day),
+            hour,
+            minute: value(self.minute),
+            second: value(self.second),
+        }
+    }
+}
+
+/// Turns a packed BCD byte like `0x59` into `59`.
+pub fn bcd_to_binary(bcd: u8) -> u8 {
+    (bcd >> 4) * 10 + (bcd & 0xf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycles_to_ns_does_not_overflow() {
+        assert_eq!(cycles_to_ns(1_193_182, 1_193_182), NANOS_PER_SEC);
+        assert_eq!(cycles_to_ns(3, 3_000_000_000), 1);
+        // a year at 4GHz
+        let cycles = 4_000_000_000 * 3600 * 24 * 365;
+        assert_eq!(
+            cycles_to_ns(cycles, 4_000_000_000),
+            3600 * 24 * 365 * NANOS_PER_SEC
+        );
+    }
+
+    #[test]
+    fn narrow_counter_wraps() {
+        // 16 bits at 1MHz, like a pit counting down turned around
+        let mut clock = Monotonic::new(1_000_000, 0xffff, 0xff00);
+        assert_eq!(clock.update(0xffff), 255_000);
+        assert_eq!(clock.update(0x0010), 272_000);
+        // bits above the mask are ignored
+        assert_eq!(clock.update(0x1_0020), 288_000);
+    }
+
+    #[test]
+    fn switch_continues_from_the_old_time() {
+        let mut clock = Monotonic::new(1_000, u64::MAX, 5);
+        assert_eq!(clock.update(1_005), NANOS_PER_SEC);
+        clock.switch(2_000_000, u32::MAX as u64, 0xffff_fff0);
+        assert_eq!(clock.ns(), NANOS_PER_SEC);
+        assert_eq!(clock.update(0x30), NANOS_PER_SEC + 32_000);
+    }
+
+    #[test]
+    fn unix_timestamps() {
+        let epoch = DateTime::from_unix(0);
+        assert_eq!(epoch.to_string(), "1970-01-01 00:00:00");
+        assert_eq!(epoch.unix_timestamp(), 0);
+
+        let leap_day = DateTime {
+            year: 2024,
+            month: 2,
+            day: 29,
+            hour: 13,
+            minute: 37,
+            second: 42,
+        };
+        assert_eq!(leap_day.unix_timestamp(), 1_709_213_862);
+        assert_eq!(DateTime::from_unix(1_709_213_862), leap_day);
+        // 2100 is no leap year, march follows february 28th
+        assert_eq!(
+            DateTime::from_unix(4_107_542_399).to_string(),
+            "2100-02-28 23:59:59"
+        );
+        assert_eq!(
+            DateTime::from_unix(4_107_542_400).to_string(),
+            "2100-03-01 00:00:00"
+        );
+    }
+
+    #[test]
+    fn round_trip_every_day() {
+        for days in 0..200 * 365 {
+            let secs = days * SECS_PER_DAY + 86_399;
+            assert_eq!(DateTime::from_unix(secs).unix_timestamp(), secs);
+        }
+    }
+
+    #[test]
+    fn decode_bcd_12_hour() {
+        let registers = RtcRegisters {
+            second: 0x05,
+            minute: 0x59,
+            hour: 0x12,
+            day: 0x31,
+            month: 0x12,
+            year: 0x99,
+            century: Some(0x19),
+            status_b: 0,
+        };
+        // 12 AM
+        assert_eq!(registers.decode().to_string(), "1999-12-31 00:59:05");
+        let pm = RtcRegisters {
+            hour: 0x11 | 0x80,
+            ..registers
+        };
+        assert_eq!(pm.decode().hour, 23);
+        let noon = RtcRegisters {
+            hour: 0x12 | 0x80,
+            ..registers
+        };
+        assert_eq!(noon.decode().hour, 12);
+    }
+
+    #[test]
+    fn decode_binary_24_hour() {
+        let registers = RtcRegisters {
+            second: 7,
+            minute: 30,
+            hour: 18,
+            day: 19,
+            month: 10,
+            year: 26,
+            century: None,
+            status_b: RtcRegisters::BINARY | RtcRegisters::HOUR_24,
+        };
+        assert_eq!(registers.decode().to_string(), "2026-10-19 18:30:07");
+    }
+}
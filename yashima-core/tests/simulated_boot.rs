@@ -0,0 +1,351 @@
+// Boots the memory subsystem against synthetic memory maps the same way `main` does it on real
+// hardware: the bootstrap heap goes into the first usable block, the page bitmap is built inside of
+// it and the heap marks itself as used.
+//
+// Physical memory is faked by a host buffer that backs just the bootstrap heap. The hhdm offset is
+// picked so the physical start of the heap lands at the start of that buffer.
+#![feature(allocator_api)]
+
+use proptest::prelude::*;
+use yashima_core::mem::bitmap::{create_bitmap, Bitmap};
+use yashima_core::mem::bootstrap_allocator::{
+    find_memblock, init_bootstrap_alloc, BootstrapAllocator, BOOTSTRAP_HEAP_SIZE,
+};
+use yashima_core::mem::page::PageSize;
+use yashima_core::mem::{calc_mem_available, MemoryMapEntry, PageFrameAllocator};
+use yashima_core::paging::PhysAddr;
+
+const KB4: u64 = PageSize::KB4 as u64;
+const MB: u64 = 1 << 20;
+const GB: u64 = 1 << 30;
+
+/// Entry types as limine reports them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Usable,
+    Reserved,
+    AcpiReclaimable,
+    AcpiNvs,
+    BadMemory,
+    BootloaderReclaimable,
+    KernelAndModules,
+    Framebuffer,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    base: u64,
+    length: u64,
+    kind: Kind,
+}
+
+impl MemoryMapEntry for Entry {
+    fn base(&self) -> u64 {
+        self.base
+    }
+
+    fn length(&self) -> u64 {
+        self.length
+    }
+
+    fn is_usable(&self) -> bool {
+        self.kind == Kind::Usable
+    }
+}
+
+/// A synthetic memory map, the entries stay in the order they were added.
+#[derive(Debug, Clone, Default)]
+struct MemoryMap(Vec<Entry>);
+
+impl MemoryMap {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn with(mut self, kind: Kind, base: u64, length: u64) -> Self {
+        self.0.push(Entry { base, length, kind });
+        self
+    }
+
+    fn usable(self, base: u64, length: u64) -> Self {
+        self.with(Kind::Usable, base, length)
+    }
+
+    fn reserved(self, base: u64, length: u64) -> Self {
+        self.with(Kind::Reserved, base, length)
+    }
+
+    /// The shape limine hands the kernel.
+    fn entries(&self) -> Vec<&Entry> {
+        self.0.iter().collect()
+    }
+
+    /// What a page should look like in the bitmap, worked out without any of the kernel code.
+    fn expect_free(&self, index: u64, heap_start: u64) -> bool {
+        let (start, end) = (index * KB4, (index + 1) * KB4);
+        let overlaps = |base: u64, length: u64| base < end && start < base + length;
+        let inside_usable = self.0.iter().any(|entry| {
+            entry.kind == Kind::Usable && entry.base <= start && end <= entry.base + entry.length
+        });
+        let overlaps_reserved = self
+            .0
+            .iter()
+            .any(|entry| entry.kind != Kind::Usable && overlaps(entry.base, entry.length));
+        inside_usable && !overlaps_reserved && !overlaps(heap_start, BOOTSTRAP_HEAP_SIZE as u64)
+    }
+}
+
+/// Stands in for the hhdm: `size` bytes of physical memory at `phys_base`, backed by a host
+/// buffer.
+struct FakeHhdm {
+    buffer: Vec<u8>,
+    phys_base: u64,
+}
+
+impl FakeHhdm {
+    fn new(phys_base: u64, size: usize) -> Self {
+        FakeHhdm {
+            buffer: vec![0; size],
+            phys_base,
+        }
+    }
+
+    fn offset(&self) -> u64 {
+        (self.buffer.as_ptr() as u64).wrapping_sub(self.phys_base)
+    }
+
+    fn contains(&self, ptr: *const u8, len: usize) -> bool {
+        let range = self.buffer.as_ptr_range();
+        range.start <= ptr && ptr.wrapping_add(len) <= range.end
+    }
+}
+
+struct Boot {
+    // declared before the hhdm, so it's dropped while its memory is still around
+    bitmap: Vec<u8, BootstrapAllocator>,
+    heap_start: u64,
+    hhdm: FakeHhdm,
+}
+
+impl Boot {
+    fn is_free(&self, index: u64) -> bool {
+        self.bitmap[index as usize / 8] & (1 << (index % 8)) == 0
+    }
+
+    fn page_count(&self) -> u64 {
+        self.bitmap.len() as u64 * 8
+    }
+}
+
+fn boot(map: &MemoryMap) -> Boot {
+    let entries = map.entries();
+    // back exactly the block the kernel is going to pick
+    let heap_start =
+        find_memblock(BOOTSTRAP_HEAP_SIZE, &entries, 1).expect("no space for the bootstrap heap");
+    let hhdm = FakeHhdm::new(heap_start, BOOTSTRAP_HEAP_SIZE);
+
+    let b_alloc = init_bootstrap_alloc(&entries, hhdm.offset());
+    assert_eq!(b_alloc.start() as *const u8, hhdm.buffer.as_ptr());
+    let mut bitmap = create_bitmap(&entries, b_alloc);
+    Bitmap::new(&mut bitmap).mark_range_used(PhysAddr::new(heap_start), BOOTSTRAP_HEAP_SIZE as u64);
+
+    Boot {
+        bitmap,
+        heap_start,
+        hhdm,
+    }
+}
+
+/// Checks the bitmap against the memory map, page by page.
+fn check(map: &MemoryMap, boot: &Boot) {
+    assert!(boot.hhdm.contains(boot.bitmap.as_ptr(), boot.bitmap.len()));
+    let mem_available = calc_mem_available(&map.entries());
+    assert_eq!(boot.page_count(), mem_available / KB4 / 8 * 8);
+    for index in 0..boot.page_count() {
+        assert_eq!(
+            boot.is_free(index),
+            map.expect_free(index, boot.heap_start),
+            "page {:#x} with heap at {:#x} in {map:#x?}",
+            index * KB4,
+            boot.heap_start
+        );
+    }
+}
+
+/// Roughly what limine reports for QEMU with 128Mb of RAM.
+fn qemu_128mb() -> MemoryMap {
+    MemoryMap::new()
+        .usable(0, 0x9f000)
+        .reserved(0x9f000, 0x61000)
+        .usable(0x100000, 0x7000000)
+        .with(Kind::BootloaderReclaimable, 0x7100000, 0x200000)
+        .with(Kind::KernelAndModules, 0x7300000, 0x100000)
+        .usable(0x7400000, 0x6e0000)
+        .with(Kind::AcpiReclaimable, 0x7ae0000, 0x20000)
+        .with(Kind::AcpiNvs, 0x7fe0000, 0x20000)
+        .with(Kind::Framebuffer, 0xfd000000, 0x300000)
+        .reserved(0xfffc0000, 0x40000)
+}
+
+#[test]
+fn qemu_layout() {
+    let map = qemu_128mb();
+    let boot = boot(&map);
+    assert_eq!(boot.heap_start, 0x100000);
+    // the reserved bios area ends right at 4Gb
+    assert_eq!(boot.page_count(), 4 * GB / KB4);
+    check(&map, &boot);
+}
+
+#[test]
+fn acpi_and_framebuffer_stay_used() {
+    let map = qemu_128mb();
+    let boot = boot(&map);
+    for entry in map.0.iter().filter(|entry| entry.kind != Kind::Usable) {
+        let first = entry.base / KB4;
+        let last = (entry.base + entry.length).div_ceil(KB4);
+        assert!(
+            (first..last).all(|index| !boot.is_free(index)),
+            "{entry:x?} is free"
+        );
+    }
+}
+
+#[test]
+fn tiny_ram_has_no_room_for_the_bootstrap_heap() {
+    let map = MemoryMap::new()
+        .usable(0, 0x9f000)
+        .reserved(0xf0000, 0x10000)
+        .usable(MB, 15 * MB);
+    assert_eq!(find_memblock(BOOTSTRAP_HEAP_SIZE, &map.entries(), 1), None);
+}
+
+#[test]
+#[should_panic(expected = "couldn't find space for bootstrap heap")]
+fn tiny_ram_stops_the_boot() {
+    let map = MemoryMap::new().usable(0, 32 * MB);
+    init_bootstrap_alloc(&map.entries(), 0);
+}
+
+#[test]
+fn heap_skips_blocks_that_are_too_small() {
+    let map = MemoryMap::new()
+        .usable(0, 0x9f000)
+        .usable(MB, 32 * MB)
+        // hole up to 1Gb
+        .usable(GB, 256 * MB)
+        .with(Kind::BadMemory, GB + 256 * MB, MB);
+    let boot = boot(&map);
+    assert_eq!(boot.heap_start, GB);
+    assert!(!boot.is_free(512 * MB / KB4), "page in the hole is free");
+    check(&map, &boot);
+}
+
+#[test]
+fn heap_avoids_overlapping_reserved_entries() {
+    // firmware reports a reserved block in the middle of a usable one
+    let map = MemoryMap::new().usable(MB, 255 * MB).reserved(16 * MB, MB);
+    let boot = boot(&map);
+    assert_eq!(boot.heap_start, 17 * MB);
+    assert!(!boot.is_free(16 * MB / KB4));
+    check(&map, &boot);
+}
+
+#[test]
+fn bitmap_covers_entries_reaching_above_higher_ones() {
+    // the entry with the highest base isn't the one that ends last
+    let map = MemoryMap::new().usable(0, 512 * MB).reserved(256 * MB, KB4);
+    assert_eq!(calc_mem_available(&map.entries()), 512 * MB);
+    let boot = boot(&map);
+    assert_eq!(boot.page_count(), 512 * MB / KB4);
+    check(&map, &boot);
+}
+
+#[test]
+fn ram_above_4gb() {
+    let map = MemoryMap::new()
+        .usable(0, 0x9f000)
+        .usable(MB, 3 * GB - MB)
+        // pci hole with the framebuffer in it
+        .with(Kind::Framebuffer, 3 * GB + 512 * MB, 8 * MB)
+        .reserved(4 * GB - 256 * KB4, 256 * KB4)
+        .usable(4 * GB, 2 * GB);
+    let boot = boot(&map);
+    assert_eq!(boot.page_count(), 6 * GB / KB4);
+    assert!(boot.is_free(5 * GB / KB4));
+    assert!(!boot.is_free((3 * GB + 512 * MB) / KB4));
+    check(&map, &boot);
+}
+
+#[test]
+fn frames_come_from_usable_memory_only() {
+    let map = MemoryMap::new()
+        .usable(0, 80 * MB)
+        .with(Kind::AcpiNvs, 80 * MB, MB)
+        .usable(81 * MB, 3 * MB);
+    let mut boot = boot(&map);
+    let heap_start = boot.heap_start;
+    let mut allocator = Bitmap::new(&mut boot.bitmap);
+    let mut count = 0;
+    while let Some(page) = allocator.allocate_frame() {
+        assert!(
+            map.expect_free(page.start.as_u64() / KB4, heap_start),
+            "{page:?}"
+        );
+        count += 1;
+    }
+    // everything but the heap and the acpi block
+    assert_eq!(count, (83 * MB - BOOTSTRAP_HEAP_SIZE as u64) / KB4);
+}
+
+/// Usable and reserved entries laid out back to back with holes in between, plus reserved entries
+/// thrown on top of them.
+fn memory_map() -> impl Strategy<Value = MemoryMap> {
+    let kind = prop_oneof![
+        4 => Just(Kind::Usable),
+        1 => Just(Kind::Reserved),
+        1 => Just(Kind::AcpiReclaimable),
+        1 => Just(Kind::Framebuffer),
+    ];
+    let segments = prop::collection::vec((kind, 1u64..160, 0u64..4), 1..8);
+    let overlapping = prop::collection::vec((0u64..1024, 1u64..64), 0..4);
+    (segments, overlapping).prop_map(|(segments, overlapping)| {
+        let mut map = MemoryMap::new();
+        let mut base = 0;
+        for (kind, length, hole) in segments {
+            // in pages, so they don't all come out page aligned
+            map = map.with(kind, base, length * MB + KB4 / 2);
+            base += (length + hole) * MB + KB4 / 2;
+        }
+        for (base, length) in overlapping {
+            map = map.reserved(base * MB / 2, length * KB4);
+        }
+        map
+    })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(16))]
+
+    #[test]
+    fn bitmap_matches_the_memory_map(map in memory_map()) {
+        prop_assume!(find_memblock(BOOTSTRAP_HEAP_SIZE, &map.entries(), 1).is_some());
+        let boot = boot(&map);
+        check(&map, &boot);
+    }
+
+    #[test]
+    fn heap_lies_inside_usable_memory(map in memory_map()) {
+        let entries = map.entries();
+        let Some(start) = find_memblock(BOOTSTRAP_HEAP_SIZE, &entries, 1) else {
+            return Ok(());
+        };
+        let end = start + BOOTSTRAP_HEAP_SIZE as u64;
+        prop_assert!(map.0.iter().any(|entry| {
+            entry.kind == Kind::Usable && entry.base <= start && end <= entry.base + entry.length
+        }), "heap at {:#x} is outside of usable memory", start);
+        prop_assert!(map.0.iter().all(|entry| {
+            entry.kind == Kind::Usable || entry.base + entry.length <= start || end <= entry.base
+        }), "heap at {:#x} overlaps reserved memory", start);
+    }
+}